@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use store::Store;
+
+const ADMINS_COLLECTION: &'static str = "admins";
+
+// Slack users who can manage everyone else through `admin` commands. The first ones come from the
+// ADMINS environment variable, and they can then grant the role to others.
+pub struct Admins {
+    store: Arc<Store>
+}
+
+impl Admins {
+    pub fn new(store: Arc<Store>) -> Admins {
+        Admins {
+            store: store
+        }
+    }
+
+    // Returns false if the user was already an admin
+    pub fn grant(&self, uid: &str) -> Result<bool, Box<Error>> {
+        if self.is_admin(uid)? {
+            return Ok(false);
+        }
+        self.store.insert_one(ADMINS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        Ok(true)
+    }
+
+    // Returns false if the user wasn't an admin to begin with
+    pub fn revoke(&self, uid: &str) -> Result<bool, Box<Error>> {
+        let removed = self.store.delete_many(ADMINS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        Ok(removed > 0)
+    }
+
+    pub fn is_admin(&self, uid: &str) -> Result<bool, Box<Error>> {
+        let admin = self.store.find_one(ADMINS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        Ok(admin.is_some())
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use history::{CardEvent, EventStore};
+use scheduler::Job;
+use slack_transport::{Notification, NotificationSender};
+use templates::{Templates, DEFAULT_LANGUAGE};
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+const REPORT_INTERVAL_DAYS: i64 = 7;
+
+pub struct ListStats {
+    pub list: String,
+    pub cards: usize,
+    pub median: Duration,
+    pub p90: Duration
+}
+
+pub struct StalledCard {
+    pub name: String,
+    pub list: String,
+    pub since: DateTime<Utc>
+}
+
+pub struct Report {
+    pub days: i64,
+    pub lists: Vec<ListStats>,
+    pub published: Vec<(String, usize)>,
+    pub stalled: Vec<StalledCard>,
+    pub stalled_days: i64
+}
+
+// Nearest-rank percentile over durations that are already sorted
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len() + 99) / 100;
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+fn format_duration(duration: &Duration) -> String {
+    let hours = duration.num_hours();
+    if hours >= 24 {
+        format!("{}d {}h", hours / 24, hours % 24)
+    }
+    else if hours > 0 {
+        format!("{}h", hours)
+    }
+    else {
+        format!("{}m", duration.num_minutes())
+    }
+}
+
+impl Report {
+    pub fn format(&self, templates: &Templates) -> String {
+        let days = self.days.to_string();
+        let stalled_days = self.stalled_days.to_string();
+        let mut lines = vec![templates.render("report.header", &[("days", &days)])];
+
+        lines.push(templates.render("report.lists", &[]));
+        if self.lists.is_empty() {
+            lines.push(templates.render("report.no_lists", &[]));
+        }
+        for stats in self.lists.iter() {
+            lines.push(templates.render("report.list", &[("list", &stats.list), ("median", &format_duration(&stats.median)),
+                ("p90", &format_duration(&stats.p90)), ("cards", &stats.cards.to_string())]));
+        }
+
+        lines.push(templates.render("report.published", &[]));
+        if self.published.is_empty() {
+            lines.push(templates.render("report.none_published", &[]));
+        }
+        for &(ref writer, count) in self.published.iter() {
+            lines.push(templates.render("report.writer", &[("writer", writer), ("count", &count.to_string())]));
+        }
+
+        lines.push(templates.render("report.stalled", &[("days", &stalled_days)]));
+        if self.stalled.is_empty() {
+            lines.push(templates.render("report.none_stalled", &[]));
+        }
+        for card in self.stalled.iter() {
+            lines.push(templates.render("report.stalled_card", &[("card", &card.name), ("list", &card.list), ("since", &templates.format_date("date.day", &card.since))]));
+        }
+
+        lines.join("\n")
+    }
+}
+
+pub struct Analytics {
+    events: EventStore,
+    published_list: String,
+    stalled_days: i64
+}
+
+impl Analytics {
+    pub fn new(events: EventStore, published_list: &str, stalled_days: i64) -> Analytics {
+        Analytics {
+            events: events,
+            published_list: published_list.to_lowercase(),
+            stalled_days: stalled_days
+        }
+    }
+
+    pub fn report(&self, days: i64) -> Result<Report, Box<Error>> {
+        let now = Utc::now();
+
+        let moves : Vec<CardEvent> = self.events.since(days)?.into_iter().filter(|event| event.is_move()).collect();
+
+        let mut durations : HashMap<String, Vec<Duration>> = HashMap::new();
+        let mut published : HashMap<String, usize> = HashMap::new();
+        let mut stalled = Vec::new();
+
+        // A card's time in a list runs from the move that put it there to the move that took it out. Moves recorded
+        // before that was kept with them can only be paired up with an earlier move inside the window.
+        let mut last_move : HashMap<&str, &CardEvent> = HashMap::new();
+        for event in moves.iter() {
+            let entered = event.entered.or_else(|| last_move.get(&event.card_id[..])
+                .filter(|previous| previous.to_list == event.from_list)
+                .map(|previous| previous.date));
+            if let Some(entered) = entered {
+                durations.entry(event.from_list.clone().unwrap()).or_insert(Vec::new()).push(event.date.signed_duration_since(entered));
+            }
+            last_move.insert(&event.card_id[..], event);
+
+            if event.to_list.as_ref().map(|list| list.to_lowercase()) == Some(self.published_list.clone()) {
+                for writer in event.members.iter() {
+                    *published.entry(writer.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // Whether a card has stalled only depends on where it was moved last
+        for last in self.events.latest_moves()? {
+            let last_list = last.to_list.clone().unwrap();
+            if last_list.to_lowercase() != self.published_list && now.signed_duration_since(last.date) >= Duration::days(self.stalled_days) {
+                stalled.push(StalledCard {
+                    name: last.card_name,
+                    list: last_list,
+                    since: last.date
+                });
+            }
+        }
+
+        let mut lists : Vec<ListStats> = durations.into_iter().map(|(list, mut list_durations)| {
+            list_durations.sort();
+            ListStats {
+                list: list,
+                cards: list_durations.len(),
+                median: percentile(&list_durations, 50),
+                p90: percentile(&list_durations, 90)
+            }
+        }).collect();
+        // Slowest lists first, since those are the bottlenecks
+        lists.sort_by(|a, b| b.median.cmp(&a.median));
+
+        let mut published : Vec<(String, usize)> = published.into_iter().collect();
+        published.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        stalled.sort_by(|a, b| a.since.cmp(&b.since));
+
+        Ok(Report {
+            days: days,
+            lists: lists,
+            published: published,
+            stalled: stalled,
+            stalled_days: self.stalled_days
+        })
+    }
+}
+
+pub struct WeeklyReportJob {
+    analytics: Analytics,
+    channel: String,
+    notifications: NotificationSender,
+    templates: Templates
+}
+
+impl WeeklyReportJob {
+    pub fn new(analytics: Analytics, channel: &str, notifications: NotificationSender, templates: Templates) -> WeeklyReportJob {
+        WeeklyReportJob {
+            analytics: analytics,
+            channel: channel.to_string(),
+            notifications: notifications,
+            templates: templates
+        }
+    }
+}
+
+impl Job for WeeklyReportJob {
+    fn name(&self) -> &str {
+        "weekly-report"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::days(REPORT_INTERVAL_DAYS)
+    }
+
+    fn run(&self) -> Result<(), Box<Error>> {
+        let report = self.analytics.report(REPORT_INTERVAL_DAYS)?;
+        self.notifications.send(Notification {
+            channel: self.channel.clone(),
+            text: report.format(&self.templates),
+            language: DEFAULT_LANGUAGE.to_string(),
+            card: None
+        })
+    }
+}
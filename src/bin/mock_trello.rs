@@ -0,0 +1,108 @@
+extern crate chrono;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+#[macro_use]
+extern crate serde_json;
+extern crate tiny_http;
+extern crate url;
+
+#[path = "../mock_board.rs"]
+mod mock_board;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+use mock_board::{MockBoard, query_param};
+
+use serde_json::Value;
+use tiny_http::{Server, Request, Response, Header};
+
+const DEFAULT_ADDRESS: &'static str = "127.0.0.1:4000";
+const DEFAULT_BOARD: &'static str = include_str!("../../fixtures/mock_board.json");
+// Lets TRELLO_API_URL point at the mock with or without the API version, like the real https://api.trello.com/1
+const API_VERSION_PREFIX: &'static str = "/1/";
+
+fn respond(request: Request, status: u16, body: &str) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(body).with_status_code(status).with_header(header);
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to request: {}", e);
+    }
+}
+
+// Changes to the board are scripted through /mock/..., everything else is answered the way Trello would
+fn script(board: &mut MockBoard, method: &str, path: &str, query: &str) -> Result<String, String> {
+    let param = |name: &str| query_param(query, name).ok_or(format!("Missing the {} parameter", name));
+
+    match (method, path) {
+        ("GET", "/mock/board") => Ok(board.state().to_string()),
+        ("POST", "/mock/cards") => {
+            let members = query_param(query, "members").unwrap_or(String::new());
+            let members : Vec<&str> = members.split(',').filter(|member| !member.is_empty()).collect();
+            let id = board.add_card(&param("shortLink")?, &param("name")?, &param("list")?, &members)?;
+            Ok(json!({ "id": id }).to_string())
+        }
+        ("POST", "/mock/move") => {
+            board.move_card(&param("member")?, &param("card")?, &param("list")?)?;
+            Ok("{}".to_string())
+        }
+        ("POST", "/mock/comment") => {
+            board.comment(&param("member")?, &param("card")?, &param("text")?)?;
+            Ok("{}".to_string())
+        }
+        _ => Err(format!("Unknown mock command {} {}", method, path))
+    }
+}
+
+fn load_board() -> MockBoard {
+    let board_json = match env::var("MOCK_TRELLO_BOARD") {
+        Ok(path) => {
+            let mut board_json = String::new();
+            File::open(&path).and_then(|mut file| file.read_to_string(&mut board_json)).expect(&format!("Could not read board file {}", path));
+            board_json
+        }
+        Err(_) => DEFAULT_BOARD.to_string()
+    };
+
+    let board : Value = serde_json::from_str(&board_json).expect("Board file is not valid JSON");
+    MockBoard::from_json(&board).expect("Board file is not a valid board")
+}
+
+fn main() {
+    env_logger::init();
+
+    let address = env::var("MOCK_TRELLO_ADDRESS").unwrap_or(DEFAULT_ADDRESS.to_string());
+    let mut board = load_board();
+
+    let server = Server::http(&address[..]).expect("Mock Trello server error");
+    info!("Serving board {} on http://{}.", board.id, address);
+
+    for request in server.incoming_requests() {
+        let method = request.method().to_string().to_uppercase();
+        let url = request.url().to_string();
+        let route = if url.starts_with(API_VERSION_PREFIX) { &url[API_VERSION_PREFIX.len() - 1..] } else { &url[..] };
+        let (path, query) = match route.find('?') {
+            Some(index) => (&route[..index], &route[index + 1..]),
+            None => (route, "")
+        };
+        info!("{} {}", method, path);
+
+        if path.starts_with("/mock/") {
+            match script(&mut board, &method, path, query) {
+                Ok(body) => respond(request, 200, &body),
+                Err(e) => {
+                    warn!("{}", e);
+                    respond(request, 400, &json!({ "error": e }).to_string())
+                }
+            }
+        }
+        else {
+            match board.handle(&method, route) {
+                Ok(body) => respond(request, 200, &body),
+                Err(status) => respond(request, status, &json!({ "error": format!("No mock response for {} {}", method, path) }).to_string())
+            }
+        }
+    }
+}
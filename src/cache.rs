@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct TtlCache<V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, V)>>
+}
+
+impl<V : Clone> TtlCache<V> {
+    pub fn new(ttl_secs: u64) -> TtlCache<V> {
+        TtlCache {
+            ttl: Duration::from_secs(ttl_secs),
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.get(key) {
+            Some(&(inserted, ref value)) => {
+                if inserted.elapsed() < self.ttl {
+                    return Some(value.clone());
+                }
+                true
+            }
+            None => false
+        };
+
+        // Lazily evict stale entries so the cache doesn't grow without bound
+        if expired {
+            entries.remove(key);
+        }
+
+        None
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        self.entries.lock().unwrap().insert(key.to_string(), (Instant::now(), value));
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn purge_expired(&self) {
+        let ttl = self.ttl;
+        self.entries.lock().unwrap().retain(|_, &mut (inserted, _)| inserted.elapsed() < ttl);
+    }
+}
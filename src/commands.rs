@@ -1,26 +1,252 @@
 use std::error::Error;
 use std::env;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use slack::RtmClient;
-use slack_api::MessageStandard;
-use mongodb::db::{Database, ThreadedDatabase};
-use bson::Bson;
+use slack_transport::{SlackOutput, IncomingMessage};
+use store::Store;
+use bson::{Bson, Document};
+use supervisor::{SupervisorHandle, WorkerState};
+use trello::{BoardHandler, CardHandler};
+use trello_models::{Card, Action, ActionData, ListData};
+use history::{CardEvent, EventStore};
+use analytics::Analytics;
+use admins::Admins;
+use links::Links;
+use reminders::ReminderPreferences;
+use sinks::NotificationSinks;
+use subscriptions::{Subscription, Subscriptions};
+use templates::{Templates, LanguagePreferences};
+use chrono::Duration;
+use chrono::prelude::*;
+
+// Long replies are cut off so a busy list doesn't flood the channel
+const MAX_LISTED_CARDS: usize = 25;
+const MAX_HISTORY_ENTRIES: usize = 15;
+const MAX_EXPORTED_EVENTS: usize = 200;
+const EXPORT_DAYS: i64 = 7;
+const DEFAULT_STATS_DAYS: i64 = 30;
+const DEFAULT_SNOOZE_DAYS: i64 = 7;
+
+fn card_link(card: &Card) -> String {
+    if card.url.is_empty() {
+        format!("_{}_", card.name)
+    }
+    else {
+        format!("<{}|{}>", card.url, card.name)
+    }
+}
+
+// Replies are written in the language of whoever sent the command
+fn reply(sender: &SlackOutput, channel: &str, templates: &Templates, name: &str, variables: &[(&str, &str)]) -> Result<(), Box<Error>> {
+    sender.send_message(channel, &templates.render(name, variables)[..])
+}
+
+fn describe_history_action(action: &Action, templates: &Templates) -> String {
+    let list_name = |list: Option<&ListData>| list.and_then(|list| list.name.clone()).unwrap_or("?".to_string());
+    let date = templates.format_date("date.full", &action.date);
+    let actor = &action.creator.username[..];
+
+    match action.data {
+        ActionData::CreateCard(ref data) | ActionData::CopyCard(ref data) => templates.render("event.created", &[("date", &date), ("list", &list_name(data.list.as_ref())), ("actor", actor)]),
+        ActionData::UpdateCard(ref data) => templates.render("event.moved", &[("date", &date), ("from", &list_name(data.list_before.as_ref())), ("to", &list_name(data.list_after.as_ref())), ("actor", actor)]),
+        _ => templates.render("event.other", &[("date", &date), ("type", &action.action_type), ("actor", actor)])
+    }
+}
+
+fn describe_event(event: &CardEvent, templates: &Templates) -> String {
+    let date = templates.format_date("date.full", &event.date);
+    let description = match (&event.from_list, &event.to_list) {
+        (&Some(ref from), &Some(ref to)) => templates.render("event.moved", &[("date", &date), ("from", from), ("to", to), ("actor", &event.actor)]),
+        _ => templates.render("event.other", &[("date", &date), ("type", &event.action_type), ("actor", &event.actor)])
+    };
+    if event.recipients.is_empty() {
+        return description;
+    }
+
+    // Slack users are kept by ID, while anyone notified through another sink is kept as sink:target
+    let recipients : Vec<String> = event.recipients.iter()
+        .map(|recipient| if recipient.contains(':') { recipient.clone() } else { format!("<@{}>", recipient) })
+        .collect();
+    templates.render("event.notified", &[("event", &description), ("recipients", &recipients.join(", "))])
+}
 
 pub struct CommandHandler {
-    db: Database
+    store: Arc<Store>,
+    events: EventStore,
+    analytics: Analytics,
+    reminders: ReminderPreferences,
+    subscriptions: Subscriptions,
+    languages: LanguagePreferences,
+    admins: Admins,
+    links: Links,
+    sinks: NotificationSinks,
+    templates: Templates,
+    supervisor: SupervisorHandle,
+    board_handler: BoardHandler,
+    card_handler: CardHandler
+}
+
+// Slack wraps addresses in its own markup, e.g. <mailto:ann@example.com|ann@example.com>, <#C024BE91L|general> or <https://...>
+fn unwrap_address(address: &str) -> String {
+    let address = address.trim_start_matches('<').trim_end_matches('>');
+    let address = address.split('|').next().unwrap();
+    address.trim_start_matches("mailto:").trim_start_matches('#').to_string()
+}
+
+// Slack mentions look like <@U024BE7LH> or <@U024BE7LH|bob>
+fn unwrap_user(mention: &str) -> String {
+    unwrap_address(mention).trim_start_matches('@').to_string()
 }
 
 impl CommandHandler {
-    pub fn new(db: Database) -> CommandHandler {
+    pub fn new(store: Arc<Store>, analytics: Analytics, supervisor: SupervisorHandle, board_handler: BoardHandler, card_handler: CardHandler, sinks: NotificationSinks, templates: Templates) -> CommandHandler {
         CommandHandler {
-            db: db
+            events: EventStore::new(Arc::clone(&store)),
+            reminders: ReminderPreferences::new(Arc::clone(&store)),
+            subscriptions: Subscriptions::new(Arc::clone(&store)),
+            languages: LanguagePreferences::new(Arc::clone(&store)),
+            admins: Admins::new(Arc::clone(&store)),
+            links: Links::new(Arc::clone(&store)),
+            store: store,
+            analytics: analytics,
+            sinks: sinks,
+            templates: templates,
+            supervisor: supervisor,
+            board_handler: board_handler,
+            card_handler: card_handler
+        }
+    }
+
+    fn find_card(&self, card_ref: &str, channel: &str, sender: &SlackOutput, templates: &Templates) -> Result<Option<Card>, Box<Error>> {
+        match self.card_handler.find_card(card_ref)? {
+            Some(ref card) if card.id_board != self.board_handler.id => {},
+            Some(card) => return Ok(Some(card)),
+            None => {}
+        }
+        reply(sender, channel, templates, "no_such_card", &[("card", card_ref)])?;
+        Ok(None)
+    }
+
+    fn find_card_events(&self, card_ref: &str) -> Result<Vec<CardEvent>, Box<Error>> {
+        // Resolve URLs and short links through Trello first, falling back to the raw reference for deleted cards
+        let card_id = match self.card_handler.find_card(card_ref) {
+            Ok(Some(card)) => card.id,
+            _ => card_ref.to_string()
+        };
+        let mut events = self.events.card_history(&card_id)?;
+        if events.is_empty() && card_id != card_ref {
+            events = self.events.card_history(card_ref)?;
+        }
+        Ok(events)
+    }
+
+    // Trello changes are made with articlebot's own token, so check on the user's behalf that their linked
+    // Trello account could have made the change itself: it has to be on the card or be a board admin
+    fn authorize(&self, user: &str, card: &Card, channel: &str, sender: &SlackOutput, templates: &Templates) -> Result<Option<String>, Box<Error>> {
+        let linked = match self.links.trello_account(user)? {
+            Some(linked) => linked,
+            None => {
+                reply(sender, channel, templates, "authorize.link_first", &[])?;
+                return Ok(None);
+            }
+        };
+
+        let membership = match self.board_handler.find_membership(&linked)? {
+            Some(membership) => membership,
+            None => {
+                reply(sender, channel, templates, "authorize.not_a_member", &[("username", &linked)])?;
+                return Ok(None);
+            }
+        };
+
+        if membership.member_type == "admin" || card.id_members.contains(&membership.id_member) {
+            Ok(Some(membership.member.username))
+        }
+        else {
+            reply(sender, channel, templates, "authorize.not_allowed", &[("card", &card.name)])?;
+            Ok(None)
+        }
+    }
+
+    // Stops a Slack user tracking anyone, returning the Slack document they were tracking with if there was one
+    fn stop_tracking(&self, tracker: &str) -> Result<Option<Document>, Box<Error>> {
+        let sdoc = match self.store.find_one_and_delete("slack", doc! {
+            "uid": tracker
+        })? {
+            Some(sdoc) => sdoc,
+            None => return Ok(None)
+        };
+
+        let trello_lookup_old = doc! {
+            "name": sdoc.get_str("tracking").unwrap()
+        };
+        if let Some(tdoc) = self.store.find_one("trello", trello_lookup_old.clone())? {
+            // Remove our current tracker from where it was originally tracking
+            let mut trackers_old = tdoc.get_array("trackers").unwrap().clone();
+            let index = trackers_old.iter().position(|tracker_old| *tracker_old.as_str().unwrap() == tracker.to_string()).unwrap();
+            trackers_old.remove(index);
+
+            // If the Trello user has no trackers, delete it. Otherwise, update it to reflect the changes made to its changes.
+            if trackers_old.is_empty() {
+                self.store.delete_one("trello", trello_lookup_old)?;
+            }
+            else {
+                let mut tdoc_new = tdoc.clone();
+                tdoc_new.insert_bson("trackers".to_string(), Bson::Array(trackers_old));
+                self.store.update_one("trello", trello_lookup_old, tdoc_new, false)?;
+            }
+        }
+        Ok(Some(sdoc))
+    }
+
+    // Notifications about the Trello user's cards are sent to the given channel from now on
+    fn start_tracking(&self, tracker: &str, channel: &str, tracking: &str) -> Result<(), Box<Error>> {
+        // Trello collection in MongoDB (key: tracking name, other data: list of trackers)
+        let trello_lookup = doc! {
+            "name": tracking
+        };
+
+        // Delete all previous records in the Slack and Trello collections if they exist
+        let mut muted = Vec::new();
+        if let Some(sdoc) = self.stop_tracking(tracker)? {
+            // Muted cards carry over to whoever the tracker follows next
+            if let Ok(muted_old) = sdoc.get_array("muted") {
+                muted = muted_old.clone();
+            }
+        }
+
+        // Slack collection in MongoDB (key: tracker id, other data: channel id, tracking name)
+        self.store.insert_one("slack", doc! {
+            "uid": tracker,
+            "cid": channel,
+            "tracking": tracking,
+            "muted": muted
+        })?;
+
+        // Update (or create/insert) the Trello document that contains the trackers
+        if let Some(tdoc) = self.store.find_one("trello", trello_lookup.clone())? {
+            let mut trackers = tdoc.get_array("trackers").unwrap().clone();
+            trackers.push(Bson::String(tracker.to_string()));
+
+            let mut tdoc_new = tdoc.clone();
+            tdoc_new.insert_bson("trackers".to_string(), Bson::Array(trackers));
+
+            self.store.update_one("trello", trello_lookup, tdoc_new, false)?;
         }
+        else {
+            self.store.insert_one("trello", doc! {
+                "name": tracking,
+                "trackers": [tracker]
+            })?;
+        }
+        Ok(())
     }
 
-    pub fn handle_message(&self, message: MessageStandard, cli: &RtmClient) -> Result<(), Box<Error>> {
-        let text : &str = &message.text.unwrap()[..];
-        let channel : &str = &message.channel.unwrap()[..];
-        let user : &str = &message.user.unwrap()[..];
+    pub fn handle_message(&self, message: &IncomingMessage, sender: &SlackOutput) -> Result<(), Box<Error>> {
+        let text : &str = &message.text[..];
+        let channel : &str = &message.channel[..];
+        let user : &str = &message.user[..];
         let split_text: Vec<&str> = text.split(" ").collect();
         let command = split_text[0].to_lowercase();
         let args = &split_text[1..];
@@ -28,153 +254,1314 @@ impl CommandHandler {
         info!("Message from {}: {}", user, text);
         info!("Interpreting as COMMAND={} ARGUMENTS={:?}", command, args);
 
-        self.on_command(&command[..], args, user, channel, cli)
+        let templates = self.templates.in_language(&self.languages.get(user)?);
+        self.on_command(&command[..], args, user, channel, sender, &templates)
     }
 
-    fn on_command(&self, command: &str, args: &[&str], user: &str, channel: &str, cli: &RtmClient) -> Result<(), Box<Error>> {
-        let sender = cli.sender();
-
+    fn on_command(&self, command: &str, args: &[&str], user: &str, channel: &str, sender: &SlackOutput, templates: &Templates) -> Result<(), Box<Error>> {
         if command == "hello" || command == "hi" {
-            sender.send_message(channel, "Hello there.")?;
+            reply(sender, channel, templates, "hello", &[])?;
         }
         else if command == "version" {
-            sender.send_message(channel, &format!("Running v{}.", env::var("CARGO_PKG_VERSION")?)[..])?;
+            reply(sender, channel, templates, "version", &[("version", &env::var("CARGO_PKG_VERSION")?)])?;
+        }
+        else if command == "status" {
+            for status in self.supervisor.statuses() {
+                let state = templates.render(match status.state {
+                    WorkerState::Running => "status.running",
+                    WorkerState::Restarting => "status.restarting",
+                    WorkerState::Failed => "status.failed"
+                }, &[]);
+                let started = status.started.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+                let restarts = status.restarts.to_string();
+                let mut variables = vec![("name", &status.name[..]), ("state", &state[..]), ("started", &started[..]), ("restarts", &restarts[..])];
+                match status.last_error {
+                    Some(ref last_error) => {
+                        variables.push(("error", &last_error[..]));
+                        reply(sender, channel, templates, "status.worker_with_error", &variables)?;
+                    }
+                    None => reply(sender, channel, templates, "status.worker", &variables)?
+                }
+            }
         }
         else if command == "tutorial" {
-            sender.send_message(channel, "Hi, I'm articlebot, a utility designed to notify you whenever your Trello cards have been moved.")?;
-            sender.send_message(channel, "You can interact with me through commands. Commands are given in the form [COMMAND] [ARGUMENTS].")?;
-            sender.send_message(channel, "[COMMAND] is usually a single word, specifying some action. I intrepret the first word you type as your command.")?;
-            sender.send_message(channel, "[ARGUMENTS] is whatever comes after your command. Some commands require you to specify arguments.")?;
-            sender.send_message(channel, "An valid example command is `track johndoe42`. In this case, `track` is the command and `johndoe42` is the argument.")?;
-            sender.send_message(channel, "Please type in `help` for a list of commands.")?;
+            reply(sender, channel, templates, "tutorial.1", &[])?;
+            reply(sender, channel, templates, "tutorial.2", &[])?;
+            reply(sender, channel, templates, "tutorial.3", &[])?;
+            reply(sender, channel, templates, "tutorial.4", &[])?;
+            reply(sender, channel, templates, "tutorial.5", &[])?;
+            reply(sender, channel, templates, "tutorial.6", &[])?;
         }
         else if command == "help" {
-            sender.send_message(channel, "If you have not already, type in `tutorial` for an overview of the command system.")?;
-            sender.send_message(channel, "Here is a list of valid commands and their descriptions:")?;
-            sender.send_message(channel, "`hello` and `hi` display a nice greeting.")?;
-            sender.send_message(channel, "`version` displays articlebot's version number.")?;
-            sender.send_message(channel, "`tutorial` displays an overview of how commands work.")?;
-            sender.send_message(channel, "`help` displays a list of valid commands.")?;
-            sender.send_message(channel, "`tracking` displays who you are following on Trello.")?;
-            sender.send_message(channel, "`track [USERNAME]` tells articlebot that you wish to follow card movements for [USERNAME] on Trello.")?;
-            sender.send_message(channel, "`untrack` tells articlebot to stop tracking any user you are currently following.")?;
+            reply(sender, channel, templates, "help.intro", &[])?;
+            reply(sender, channel, templates, "help.commands", &[])?;
+            reply(sender, channel, templates, "help.hello", &[])?;
+            reply(sender, channel, templates, "help.version", &[])?;
+            reply(sender, channel, templates, "help.status", &[])?;
+            reply(sender, channel, templates, "help.tutorial", &[])?;
+            reply(sender, channel, templates, "help.help", &[])?;
+            reply(sender, channel, templates, "help.tracking", &[])?;
+            reply(sender, channel, templates, "help.track", &[])?;
+            reply(sender, channel, templates, "help.untrack", &[])?;
+            reply(sender, channel, templates, "help.link", &[])?;
+            reply(sender, channel, templates, "help.move", &[])?;
+            reply(sender, channel, templates, "help.assign", &[])?;
+            reply(sender, channel, templates, "help.comment", &[])?;
+            reply(sender, channel, templates, "help.cards", &[])?;
+            reply(sender, channel, templates, "help.list", &[])?;
+            reply(sender, channel, templates, "help.card", &[])?;
+            reply(sender, channel, templates, "help.history", &[])?;
+            reply(sender, channel, templates, "help.export", &[])?;
+            reply(sender, channel, templates, "help.stats", &[])?;
+            reply(sender, channel, templates, "help.snooze", &[])?;
+            reply(sender, channel, templates, "help.reminders", &[])?;
+            reply(sender, channel, templates, "help.mute", &[])?;
+            reply(sender, channel, templates, "help.subscribe", &[("sinks", &self.sinks.names().join(", "))])?;
+            reply(sender, channel, templates, "help.unsubscribe", &[])?;
+            reply(sender, channel, templates, "help.language", &[("languages", &templates.languages().join(", "))])?;
+            reply(sender, channel, templates, "help.admin", &[])?;
         }
         else if command == "tracking" {
-            if let Some(sdoc) = self.db.collection("slack").find_one(Some(doc! {
+            if let Some(sdoc) = self.store.find_one("slack", doc! {
                 "uid": user
-            }), None)? {
-                sender.send_message(channel, &format!("You are currently tracking *{}* on Trello.", sdoc.get_str("tracking").unwrap())[..])?;
+            })? {
+                reply(sender, channel, templates, "tracking", &[("username", sdoc.get_str("tracking").unwrap())])?;
             }
             else {
-                sender.send_message(channel, "You are currently not tracking a Trello user.")?;
+                reply(sender, channel, templates, "not_tracking", &[])?;
             }
         }
         else if command == "track" {
-            let tracker = user;
             let tracking = args.join(" ");
-
-            // Slack collection in MongoDB (key: tracker id, other data: channel id, tracking name)
-            let slack_coll = self.db.collection("slack");
-            let slack_lookup = doc! {
-                "uid": tracker
+            // Notifications always go to a DM, even when the command was a mention in a channel
+            let dm = if channel.starts_with('D') {
+                channel.to_string()
+            }
+            else {
+                sender.open_dm(user)?
+            };
+            self.start_tracking(user, &dm, &tracking)?;
+            reply(sender, channel, templates, "track.done", &[("username", &tracking)])?;
+        }
+        else if command == "untrack" {
+            // The notification button names the writer it was sent for, who may no longer be the one being tracked
+            if !args.is_empty() {
+                let writer = args.join(" ");
+                let tracking = self.store.find_one("slack", doc! {
+                    "uid": user
+                })?.and_then(|sdoc| sdoc.get_str("tracking").ok().map(|tracking| tracking.to_string()));
+                if tracking.as_ref() != Some(&writer) {
+                    reply(sender, channel, templates, "untrack.other_writer", &[("username", &writer)])?;
+                    return Ok(());
+                }
+            }
+            if self.stop_tracking(user)?.is_some() {
+                reply(sender, channel, templates, "untrack.done", &[])?;
+            }
+            else {
+                reply(sender, channel, templates, "not_tracking", &[])?;
+            }
+        }
+        else if command == "link" && args.len() == 1 && args[0].to_lowercase() == "confirm" {
+            let request = match self.links.pending(user)? {
+                Some(request) => request,
+                None => {
+                    reply(sender, channel, templates, "link.confirm.none", &[])?;
+                    return Ok(());
+                }
             };
 
-            // Trello collection in MongoDB (key: tracking name, other data: list of trackers)
-            let trello_coll = self.db.collection("trello");
-            let trello_lookup = doc! {
-                "name": &tracking
+            // The code has to have been commented by the Trello account itself, which only its owner can do
+            let trello = request.trello.to_lowercase();
+            let proven = self.board_handler.get_comments(request.requested)?.iter().any(|action| {
+                action.creator.username.to_lowercase() == trello && match action.data {
+                    ActionData::CommentCard(ref comment) => comment.text.contains(&request.code[..]),
+                    _ => false
+                }
+            });
+            if proven {
+                self.links.confirm(user, &request.trello)?;
+                info!("{} linked their Slack account to {}.", user, request.trello);
+                reply(sender, channel, templates, "link.done", &[("username", &request.trello)])?;
+            }
+            else {
+                reply(sender, channel, templates, "link.confirm.not_found", &[("username", &request.trello), ("code", &request.code)])?;
+            }
+        }
+        else if command == "link" {
+            // Without a username, try the Slack username in case it's the same on Trello
+            let slack_name = if args.is_empty() {
+                sender.lookup_user(user)?.map(|slack_user| slack_user.name)
+            }
+            else {
+                None
+            };
+            let username = match (args.first(), slack_name.as_ref()) {
+                (Some(username), _) => username.trim_start_matches('@'),
+                (None, Some(slack_name)) => &slack_name[..],
+                (None, None) => {
+                    reply(sender, channel, templates, "link.usage", &[])?;
+                    return Ok(());
+                }
             };
+            match self.board_handler.find_membership(username)? {
+                Some(membership) => {
+                    let code = self.links.request(user, &membership.member.username)?;
+                    reply(sender, channel, templates, "link.code", &[("username", &membership.member.username), ("code", &code)])?;
+                }
+                None => {
+                    reply(sender, channel, templates, "not_a_member", &[("username", username)])?;
+                }
+            }
+        }
+        else if command == "unlink" {
+            if self.links.unlink(user)? {
+                reply(sender, channel, templates, "unlink.done", &[])?;
+            }
+            else {
+                reply(sender, channel, templates, "unlink.none", &[])?;
+            }
+        }
+        else if command == "move" {
+            // move [CARD] to [LIST], where the list name may contain spaces
+            if args.len() < 3 || args[1].to_lowercase() != "to" {
+                reply(sender, channel, templates, "move.usage", &[])?;
+                return Ok(());
+            }
+            let list_name = args[2..].join(" ");
 
-            // Delete all previous records in the Slack and Trello collections if they exist
-            if let Some(sdoc) = slack_coll.find_one_and_delete(slack_lookup, None)? {
-                let trello_lookup_old = doc! {
-                    "name": sdoc.get_str("tracking").unwrap()
-                };
-                if let Some(tdoc) = trello_coll.find_one(Some(trello_lookup_old.clone()), None)? {
-                    // Remove our current tracker from where it was originally tracking
-                    let mut trackers_old = tdoc.get_array("trackers").unwrap().clone();
-                    let index = trackers_old.iter().position(|tracker_old| *tracker_old.as_str().unwrap() == tracker.to_string()).unwrap();
-                    trackers_old.remove(index);
-
-                    // If the Trello user has no trackers, delete it. Otherwise, update it to reflect the changes made to its changes.
-                    if trackers_old.is_empty() {
-                        trello_coll.delete_one(trello_lookup_old, None)?;
+            if let Some(card) = self.find_card(args[0], channel, sender, templates)? {
+                if let Some(username) = self.authorize(user, &card, channel, sender, templates)? {
+                    match self.board_handler.find_list(&list_name)? {
+                        Some(ref list) if list.id == card.id_list => {
+                            reply(sender, channel, templates, "move.already_there", &[("card", &card.name), ("list", &list.name)])?;
+                        }
+                        Some(list) => {
+                            self.card_handler.move_card(&card.id, &list.id)?;
+                            info!("{} ({}) moved card {} to {}.", user, username, card.id, list.name);
+                            reply(sender, channel, templates, "move.done", &[("card", &card.name), ("list", &list.name)])?;
+                        }
+                        None => {
+                            reply(sender, channel, templates, "no_such_list", &[("list", &list_name)])?;
+                        }
+                    }
+                }
+            }
+        }
+        else if command == "assign" {
+            if args.len() < 2 {
+                reply(sender, channel, templates, "assign.usage", &[])?;
+                return Ok(());
+            }
+
+            if let Some(card) = self.find_card(args[0], channel, sender, templates)? {
+                if let Some(username) = self.authorize(user, &card, channel, sender, templates)? {
+                    match self.board_handler.find_membership(args[1])? {
+                        Some(ref membership) if card.id_members.contains(&membership.id_member) => {
+                            reply(sender, channel, templates, "assign.already_on", &[("username", &membership.member.username), ("card", &card.name)])?;
+                        }
+                        Some(membership) => {
+                            self.card_handler.add_member(&card.id, &membership.id_member)?;
+                            info!("{} ({}) assigned {} to card {}.", user, username, membership.member.username, card.id);
+                            reply(sender, channel, templates, "assign.done", &[("username", &membership.member.username), ("card", &card.name)])?;
+                        }
+                        None => {
+                            reply(sender, channel, templates, "not_a_member", &[("username", args[1])])?;
+                        }
+                    }
+                }
+            }
+        }
+        else if command == "comment" {
+            if args.len() < 2 {
+                reply(sender, channel, templates, "comment.usage", &[])?;
+                return Ok(());
+            }
+
+            if let Some(card) = self.find_card(args[0], channel, sender, templates)? {
+                if let Some(username) = self.authorize(user, &card, channel, sender, templates)? {
+                    // The comment is posted as articlebot, so credit whoever actually wrote it
+                    // The comment is for everyone on the board, so it stays in the default language
+                    let text = self.templates.render("comment.trello", &[("username", &username), ("text", &args[1..].join(" "))]);
+                    self.card_handler.add_comment(&card.id, &text)?;
+                    reply(sender, channel, templates, "comment.done", &[("card", &card.name)])?;
+                }
+            }
+        }
+        else if command == "cards" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "cards.usage", &[])?;
+                return Ok(());
+            }
+
+            match self.board_handler.find_membership(args[0])? {
+                Some(membership) => {
+                    let cards = self.board_handler.get_member_cards(&membership.id_member)?;
+                    if cards.is_empty() {
+                        reply(sender, channel, templates, "cards.none", &[("username", &membership.member.username)])?;
                     }
                     else {
-                        let mut tdoc_new = tdoc.clone();
-                        tdoc_new.insert_bson("trackers".to_string(), Bson::Array(trackers_old));
-                        trello_coll.update_one(trello_lookup_old, tdoc_new, None)?;
+                        let list_names : HashMap<String, String> = self.board_handler.get_lists()?.into_iter().map(|list| (list.id, list.name)).collect();
+                        let archived_list = templates.render("cards.archived_list", &[]);
+                        let mut lines = vec![templates.render("cards.header", &[("username", &membership.member.username), ("count", &cards.len().to_string())])];
+                        for card in cards.iter().take(MAX_LISTED_CARDS) {
+                            let list_name = list_names.get(&card.id_list).unwrap_or(&archived_list);
+                            lines.push(templates.render("cards.entry", &[("card", &card_link(card)), ("list", list_name)]));
+                        }
+                        if cards.len() > MAX_LISTED_CARDS {
+                            lines.push(templates.render("more", &[("count", &(cards.len() - MAX_LISTED_CARDS).to_string())]));
+                        }
+                        sender.send_message(channel, &lines.join("\n")[..])?;
                     }
                 }
+                None => {
+                    reply(sender, channel, templates, "not_a_member", &[("username", args[0])])?;
+                }
+            }
+        }
+        else if command == "list" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "list.usage", &[])?;
+                return Ok(());
             }
+            let list_name = args.join(" ");
 
-            // Insert a new Slack document specifying the tracker's information
-            slack_coll.insert_one(doc! {
-                "uid": tracker,
-                "cid": channel,
-                "tracking": &tracking
-            }, None)?;
+            match self.board_handler.find_list(&list_name)? {
+                Some(list) => {
+                    let cards = self.board_handler.get_list_cards(&list.id)?;
+                    if cards.is_empty() {
+                        reply(sender, channel, templates, "list.empty", &[("list", &list.name)])?;
+                    }
+                    else {
+                        let usernames : HashMap<String, String> = self.board_handler.get_memberships()?.into_iter().map(|membership| (membership.id_member, membership.member.username)).collect();
+                        let mut lines = vec![templates.render("list.header", &[("list", &list.name), ("count", &cards.len().to_string())])];
+                        for card in cards.iter().take(MAX_LISTED_CARDS) {
+                            let members : Vec<&str> = card.id_members.iter().filter_map(|id| usernames.get(id)).map(|username| &username[..]).collect();
+                            if members.is_empty() {
+                                lines.push(templates.render("list.entry", &[("card", &card_link(card))]));
+                            }
+                            else {
+                                lines.push(templates.render("list.entry_with_members", &[("card", &card_link(card)), ("members", &members.join(", "))]));
+                            }
+                        }
+                        if cards.len() > MAX_LISTED_CARDS {
+                            lines.push(templates.render("more", &[("count", &(cards.len() - MAX_LISTED_CARDS).to_string())]));
+                        }
+                        sender.send_message(channel, &lines.join("\n")[..])?;
+                    }
+                }
+                None => {
+                    reply(sender, channel, templates, "no_such_list", &[("list", &list_name)])?;
+                }
+            }
+        }
+        else if command == "card" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "card.usage", &[])?;
+                return Ok(());
+            }
 
-            // Update (or create/insert) the Trello document that contains the trackers
-            if let Some(tdoc) = trello_coll.find_one(Some(trello_lookup.clone()), None)? {
-                let mut trackers = tdoc.get_array("trackers").unwrap().clone();
-                trackers.push(Bson::String(tracker.to_string()));
+            if let Some(card) = self.find_card(args[0], channel, sender, templates)? {
+                let list = self.card_handler.get_list(&card.id_list)?;
+                let members = self.card_handler.get_card_members(&card)?;
+                let history = self.card_handler.get_card_history(&card.id)?;
+                let checklists = self.card_handler.get_checklists(&card.id)?;
+                let attachments = self.card_handler.get_attachments(&card.id)?;
 
-                let mut tdoc_new = tdoc.clone();
-                tdoc_new.insert_bson("trackers".to_string(), Bson::Array(trackers));
+                let render = |name: &str, variables: &[(&str, &str)]| templates.render(name, variables);
+                let mut lines = vec![render("card.title", &[("card", &card_link(&card))]), render("card.list", &[("list", &list.name)])];
+                if !members.is_empty() {
+                    let usernames : Vec<&str> = members.iter().map(|member| &member.username[..]).collect();
+                    lines.push(render("card.members", &[("members", &usernames.join(", "))]));
+                }
+                if !card.labels.is_empty() {
+                    // Unnamed labels are shown by their colour instead
+                    let labels : Vec<String> = card.labels.iter()
+                        .map(|label| if label.name.is_empty() { label.color.clone().unwrap_or("?".to_string()) } else { label.name.clone() })
+                        .collect();
+                    lines.push(render("card.labels", &[("labels", &labels.join(", "))]));
+                }
+                if let Some(due) = card.due {
+                    lines.push(render(if card.due_complete { "card.due_complete" } else { "card.due" }, &[("due", &templates.format_date("date.full", &due))]));
+                }
+                for checklist in checklists.iter() {
+                    lines.push(render("card.checklist", &[("checklist", &checklist.name), ("done", &checklist.completed().to_string()), ("total", &checklist.check_items.len().to_string())]));
+                }
+                if !attachments.is_empty() {
+                    lines.push(render("card.attachments", &[("count", &attachments.len().to_string())]));
+                }
+                if !history.is_empty() {
+                    lines.push(render("card.history", &[]));
+                    let skipped = history.len().saturating_sub(MAX_HISTORY_ENTRIES);
+                    for action in history.iter().skip(skipped) {
+                        lines.push(render("event.entry", &[("event", &describe_history_action(action, templates))]));
+                    }
+                }
+                sender.send_message(channel, &lines.join("\n")[..])?;
+            }
+        }
+        else if command == "history" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "history.usage", &[])?;
+                return Ok(());
+            }
 
-                trello_coll.update_one(trello_lookup, tdoc_new, None)?;
+            let events = self.find_card_events(args[0])?;
+            if events.is_empty() {
+                reply(sender, channel, templates, "history.none", &[("card", args[0])])?;
             }
             else {
-                trello_coll.insert_one(doc! {
-                    "name": &tracking,
-                    "trackers": [tracker]
-                }, None)?;
+                let mut lines = vec![templates.render("history.header", &[("card", &events[events.len() - 1].card_name)])];
+                let skipped = events.len().saturating_sub(MAX_HISTORY_ENTRIES);
+                for event in events.iter().skip(skipped) {
+                    lines.push(templates.render("event.entry", &[("event", &describe_event(event, templates))]));
+                }
+                sender.send_message(channel, &lines.join("\n")[..])?;
+            }
+        }
+        else if command == "export" {
+            // export [CARD] exports a single card, export on its own exports everything from the past week
+            let events = if args.is_empty() {
+                self.events.since(EXPORT_DAYS)?
             }
+            else {
+                self.find_card_events(args[0])?
+            };
 
-            sender.send_message(channel, &format!("You will now be notified when *{}*'s articles are moved in Trello.", tracking)[..])?;
+            if events.is_empty() {
+                reply(sender, channel, templates, "export.none", &[])?;
+            }
+            else {
+                let skipped = events.len().saturating_sub(MAX_EXPORTED_EVENTS);
+                let mut csv = CardEvent::csv_header().to_string();
+                for event in events.iter().skip(skipped) {
+                    csv.push_str(&format!("\n{}", event.to_csv()));
+                }
+                if skipped > 0 {
+                    reply(sender, channel, templates, "export.truncated", &[("count", &MAX_EXPORTED_EVENTS.to_string()), ("total", &events.len().to_string())])?;
+                }
+                sender.send_message(channel, &format!("```{}```", csv)[..])?;
+            }
+        }
+        else if command == "stats" {
+            let days = match args.get(0) {
+                Some(days) => match days.parse::<i64>() {
+                    Ok(days) if days > 0 => days,
+                    _ => {
+                        reply(sender, channel, templates, "stats.usage", &[])?;
+                        return Ok(());
+                    }
+                },
+                None => DEFAULT_STATS_DAYS
+            };
+
+            let report = self.analytics.report(days)?;
+            sender.send_message(channel, &report.format(templates)[..])?;
+        }
+        else if command == "snooze" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "snooze.usage", &[])?;
+                return Ok(());
+            }
+
+            let days = match args.get(1) {
+                Some(days) => match days.parse::<i64>() {
+                    Ok(days) if days > 0 => days,
+                    _ => {
+                        reply(sender, channel, templates, "snooze.days", &[])?;
+                        return Ok(());
+                    }
+                },
+                None => DEFAULT_SNOOZE_DAYS
+            };
+
+            let card = match self.find_card(args[0], channel, sender, templates)? {
+                Some(card) => card,
+                None => return Ok(())
+            };
+
+            let until = Utc::now() + Duration::days(days);
+            self.reminders.snooze(user, &card.id, until)?;
+            reply(sender, channel, templates, "snooze.done", &[("card", &card.name), ("date", &templates.format_date("date.day", &until))])?;
+        }
+        else if command == "reminders" {
+            match args.get(0).map(|setting| setting.to_lowercase()) {
+                Some(ref setting) if setting == "on" => {
+                    self.reminders.set_enabled(user, true)?;
+                    reply(sender, channel, templates, "reminders.on", &[])?;
+                }
+                Some(ref setting) if setting == "off" => {
+                    self.reminders.set_enabled(user, false)?;
+                    reply(sender, channel, templates, "reminders.off", &[])?;
+                }
+                _ => {
+                    let state = if self.reminders.is_enabled(user)? { "on" } else { "off" };
+                    reply(sender, channel, templates, "reminders.state", &[("state", state)])?;
+                }
+            }
+        }
+        else if command == "mute" || command == "unmute" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "mute.usage", &[("command", command)])?;
+                return Ok(());
+            }
+            let card = args[0];
+
+            let update = if command == "mute" {
+                doc! { "$addToSet": { "muted": card } }
+            }
+            else {
+                doc! { "$pull": { "muted": card } }
+            };
+
+            let matched = self.store.update_one("slack", doc! {
+                "uid": user
+            }, update, false)?;
+
+            if matched == 0 {
+                reply(sender, channel, templates, "not_tracking", &[])?;
+            }
+            else if command == "mute" {
+                reply(sender, channel, templates, "mute.done", &[("card", card)])?;
+            }
+            else {
+                reply(sender, channel, templates, "unmute.done", &[("card", card)])?;
+            }
+        }
+        else if command == "subscribe" {
+            if args.len() < 3 {
+                reply(sender, channel, templates, "subscribe.usage", &[])?;
+                return Ok(());
+            }
+            let trello = args[0].trim_start_matches('@');
+            let sink_name = args[1].to_lowercase();
+            let target = unwrap_address(args[2]);
+
+            // Notifications can only be sent on about your own cards, unless you're an admin
+            let linked = self.links.trello_account(user)?.map(|linked| linked.to_lowercase());
+            if linked != Some(trello.to_lowercase()) && !self.admins.is_admin(user)? {
+                reply(sender, channel, templates, "subscribe.not_allowed", &[("username", trello)])?;
+                return Ok(());
+            }
+
+            let sink = match self.sinks.get(&sink_name) {
+                Some(sink) => sink,
+                None => {
+                    reply(sender, channel, templates, "subscribe.no_sink", &[("sink", &sink_name), ("sinks", &self.sinks.names().join(", "))])?;
+                    return Ok(());
+                }
+            };
+            if let Err(reason) = sink.check_target(&target) {
+                reply(sender, channel, templates, reason, &[("target", &target)])?;
+                return Ok(());
+            }
+
+            let added = self.subscriptions.add(&Subscription {
+                trello: trello.to_string(),
+                sink: sink_name.clone(),
+                target: target.clone(),
+                creator: user.to_string()
+            })?;
+            if added {
+                reply(sender, channel, templates, "subscribe.done", &[("username", trello), ("target", &target), ("sink", &sink_name)])?;
+            }
+            else {
+                reply(sender, channel, templates, "subscribe.exists", &[("username", trello), ("target", &target)])?;
+            }
+        }
+        else if command == "unsubscribe" {
+            if args.len() < 2 {
+                reply(sender, channel, templates, "unsubscribe.usage", &[])?;
+                return Ok(());
+            }
+            let trello = args[0].trim_start_matches('@');
+            let target = unwrap_address(args[1]);
+
+            // Admins can remove anyone's subscriptions, everyone else only the ones they set up
+            let creator = if self.admins.is_admin(user)? { None } else { Some(user) };
+            if self.subscriptions.remove(trello, &target, creator)? > 0 {
+                reply(sender, channel, templates, "unsubscribe.done", &[("username", trello), ("target", &target)])?;
+            }
+            else if self.subscriptions.for_member(trello)?.iter().any(|subscription| subscription.target == target) {
+                reply(sender, channel, templates, "unsubscribe.not_allowed", &[("username", trello), ("target", &target)])?;
+            }
+            else {
+                reply(sender, channel, templates, "unsubscribe.none", &[("username", trello), ("target", &target)])?;
+            }
+        }
+        else if command == "subscriptions" {
+            let mut subscriptions = match args.first() {
+                Some(trello) => self.subscriptions.for_member(trello.trim_start_matches('@'))?,
+                None => self.subscriptions.all()?
+            };
+            // Targets such as webhook URLs let anyone who knows them post there, so everyone but admins only sees
+            // the subscriptions they set up and the ones to their own cards
+            let is_admin = self.admins.is_admin(user)?;
+            if !is_admin {
+                let linked = self.links.trello_account(user)?.map(|linked| linked.to_lowercase());
+                subscriptions.retain(|subscription| subscription.creator == user || Some(subscription.trello.to_lowercase()) == linked);
+            }
+            if subscriptions.is_empty() {
+                reply(sender, channel, templates, if is_admin { "subscriptions.none" } else { "subscriptions.none_yours" }, &[])?;
+            }
+            else {
+                let lines : Vec<String> = subscriptions.iter()
+                    .map(|subscription| templates.render("subscriptions.entry", &[("username", &subscription.trello), ("target", &subscription.target),
+                        ("sink", &subscription.sink), ("creator", &subscription.creator)]))
+                    .collect();
+                sender.send_message(channel, &lines.join("\n")[..])?;
+            }
+        }
+        else if command == "language" {
+            let languages = templates.languages();
+            match args.first().map(|language| language.to_lowercase()) {
+                None => {
+                    let current = self.languages.get(user)?;
+                    reply(sender, channel, templates, "language.current", &[("language", &current), ("languages", &languages.join(", "))])?;
+                }
+                Some(ref language) if languages.contains(language) => {
+                    self.languages.set(user, language)?;
+                    reply(sender, channel, &templates.in_language(language), "language.done", &[])?;
+                }
+                Some(language) => {
+                    reply(sender, channel, templates, "language.unknown", &[("language", &language), ("languages", &languages.join(", "))])?;
+                }
+            }
+        }
+        else if command == "admin" {
+            self.on_admin_command(args, user, channel, sender, templates)?;
+        }
+        else {
+            reply(sender, channel, templates, "unknown_command", &[("command", command)])?;
+            reply(sender, channel, templates, "unknown_command.help", &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn on_admin_command(&self, args: &[&str], user: &str, channel: &str, sender: &SlackOutput, templates: &Templates) -> Result<(), Box<Error>> {
+        if !self.admins.is_admin(user)? {
+            warn!("{} tried to use an admin command without being an admin.", user);
+            reply(sender, channel, templates, "admin.not_allowed", &[])?;
+            return Ok(());
+        }
+
+        let command = args.first().map(|command| command.to_lowercase()).unwrap_or(String::new());
+        let args = if args.is_empty() { args } else { &args[1..] };
+
+        if command == "track" {
+            if args.len() < 2 {
+                reply(sender, channel, templates, "admin.track.usage", &[])?;
+                return Ok(());
+            }
+            let target = unwrap_user(args[0]);
+            let tracking = args[1..].join(" ");
+
+            // Notifications go to the user's own DM, not wherever the admin happens to be
+            let dm = sender.open_dm(&target)?;
+            self.start_tracking(&target, &dm, &tracking)?;
+            info!("{} made {} track {}.", user, target, tracking);
+            reply(sender, channel, templates, "admin.track.done", &[("user", &target), ("username", &tracking)])?;
         }
         else if command == "untrack" {
-            let tracker = user;
-
-            // Relevant MongoDB collections
-            let slack_coll = self.db.collection("slack");
-            let trello_coll = self.db.collection("trello");
-
-            if let Some(sdoc) = slack_coll.find_one_and_delete(doc! {
-                "uid": tracker
-            }, None)? {
-                let trello_lookup_old = doc! {
-                    "name": sdoc.get_str("tracking").unwrap()
-                };
-                if let Some(tdoc) = trello_coll.find_one(Some(trello_lookup_old.clone()), None)? {
-                    // Remove our current tracker from where it was originally tracking
-                    let mut trackers_old = tdoc.get_array("trackers").unwrap().clone();
-                    let index = trackers_old.iter().position(|tracker_old| *tracker_old.as_str().unwrap() == tracker.to_string()).unwrap();
-                    trackers_old.remove(index);
-
-                    // If the Trello user has no trackers, delete it. Otherwise, update it to reflect the changes made to its changes.
-                    if trackers_old.is_empty() {
-                        trello_coll.delete_one(trello_lookup_old, None)?;
+            if args.is_empty() {
+                reply(sender, channel, templates, "admin.untrack.usage", &[])?;
+                return Ok(());
+            }
+            let target = unwrap_user(args[0]);
+
+            if self.stop_tracking(&target)?.is_some() {
+                info!("{} made {} stop tracking.", user, target);
+                reply(sender, channel, templates, "admin.untrack.done", &[("user", &target)])?;
+            }
+            else {
+                reply(sender, channel, templates, "admin.untrack.none", &[("user", &target)])?;
+            }
+        }
+        else if command == "subscriptions" {
+            // Everyone's trackers, followed by every subscription through another sink
+            let mut lines = vec![templates.render("admin.subscriptions.trackers", &[])];
+            let sdocs = self.store.find("slack", doc! {}, Some(doc! { "tracking": 1 }))?;
+            if sdocs.is_empty() {
+                lines.push(templates.render("admin.subscriptions.none", &[]));
+            }
+            for sdoc in sdocs.iter() {
+                lines.push(templates.render("admin.subscriptions.tracker", &[("user", sdoc.get_str("uid").unwrap()), ("username", sdoc.get_str("tracking").unwrap())]));
+            }
+
+            lines.push(templates.render("admin.subscriptions.subscriptions", &[]));
+            let subscriptions = self.subscriptions.all()?;
+            if subscriptions.is_empty() {
+                lines.push(templates.render("admin.subscriptions.none", &[]));
+            }
+            for subscription in subscriptions.iter() {
+                lines.push(templates.render("subscriptions.entry", &[("username", &subscription.trello), ("target", &subscription.target),
+                    ("sink", &subscription.sink), ("creator", &subscription.creator)]));
+            }
+            sender.send_message(channel, &lines.join("\n")[..])?;
+        }
+        else if command == "broadcast" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "admin.broadcast.usage", &[])?;
+                return Ok(());
+            }
+            let text = args.join(" ");
+
+            // Trackers are reached where their notifications go, and linked users get a DM opened for them.
+            // One user who can't be reached doesn't keep the message from everyone else.
+            let mut failed = 0;
+            let mut channels : HashMap<String, String> = HashMap::new();
+            for sdoc in self.store.find("slack", doc! {}, None)? {
+                channels.insert(sdoc.get_str("uid").unwrap().to_string(), sdoc.get_str("cid").unwrap().to_string());
+            }
+            for uid in self.links.all_slack_users()? {
+                if !channels.contains_key(&uid) {
+                    match sender.open_dm(&uid) {
+                        Ok(dm) => {
+                            channels.insert(uid, dm);
+                        }
+                        Err(e) => {
+                            warn!("Could not open a DM with {} to broadcast to: {}", uid, e);
+                            failed += 1;
+                        }
                     }
-                    else {
-                        let mut tdoc_new = tdoc.clone();
-                        tdoc_new.insert_bson("trackers".to_string(), Bson::Array(trackers_old));
-                        trello_coll.update_one(trello_lookup_old, tdoc_new, None)?;
+                }
+            }
+
+            // Sent straight away rather than through the notification buffer, which may only be flushed by this very thread
+            let mut reached = 0;
+            for (uid, cid) in channels.iter() {
+                match sender.post_message(cid, &text) {
+                    Ok(()) => reached += 1,
+                    Err(e) => {
+                        warn!("Could not broadcast to {}: {}", uid, e);
+                        failed += 1;
                     }
                 }
-                sender.send_message(channel, "You are no longer tracking anyone in Trello.")?;
+            }
+            info!("{} broadcast a message to {} users, {} could not be reached.", user, reached, failed);
+            if failed == 0 {
+                reply(sender, channel, templates, "admin.broadcast.done", &[("count", &reached.to_string())])?;
             }
             else {
-                sender.send_message(channel, "You are currently not tracking a Trello user.")?;
+                reply(sender, channel, templates, "admin.broadcast.partial", &[("count", &reached.to_string()), ("failed", &failed.to_string())])?;
+            }
+        }
+        else if command == "reload" {
+            match self.templates.reload() {
+                Ok(()) => reply(sender, channel, templates, "admin.reload.done", &[])?,
+                Err(e) => {
+                    warn!("Could not reload templates: {}", e);
+                    reply(sender, channel, templates, "admin.reload.failed", &[("error", &e)])?;
+                }
+            }
+        }
+        else if command == "grant" || command == "revoke" {
+            if args.is_empty() {
+                reply(sender, channel, templates, "admin.grant.usage", &[("command", &command)])?;
+                return Ok(());
+            }
+            let target = unwrap_user(args[0]);
+
+            if command == "grant" {
+                let granted = self.admins.grant(&target)?;
+                reply(sender, channel, templates, if granted { "admin.grant.done" } else { "admin.grant.exists" }, &[("user", &target)])?;
+            }
+            else if target == user {
+                reply(sender, channel, templates, "admin.revoke.self", &[])?;
+            }
+            else {
+                let revoked = self.admins.revoke(&target)?;
+                reply(sender, channel, templates, if revoked { "admin.revoke.done" } else { "admin.revoke.none" }, &[("user", &target)])?;
             }
         }
         else {
-            sender.send_message(channel, &format!("I did not understand your command `{}`.", command)[..])?;
-            sender.send_message(channel, "Try typing in `help` to see a list of available commands.")?;
+            if !command.is_empty() && command != "help" {
+                reply(sender, channel, templates, "admin.unknown", &[("command", &command)])?;
+            }
+            let lines : Vec<String> = ["admin.help.intro", "admin.help.track", "admin.help.untrack", "admin.help.subscriptions",
+                                       "admin.help.broadcast", "admin.help.reload", "admin.help.grant"].iter()
+                .map(|name| templates.render(name, &[]))
+                .collect();
+            sender.send_message(channel, &lines.join("\n")[..])?;
         }
 
         Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time;
+
+    use super::*;
+    use memory_store::MemoryStore;
+    use sinks::{SlackSink, EmailSink, WebhookSink};
+    use slack_transport::{NotificationSender, NOTIFICATION_BUFFER_SIZE};
+    use slack_fake::FakeSlack;
+    use supervisor::Supervisor;
+    use trello_client::TrelloApi;
+    use trello_fake::{FakeTrello, fixture};
+    use trello_models::Action;
+
+    use reqwest::Method;
+    use serde_json;
+
+    const BOARD_ID: &'static str = "5a0000000000000000000001";
+    const CARD_ID: &'static str = "5a3000000000000000000001";
+    const CARD_LINK: &'static str = "<https://trello.com/c/CrD1sHrt/1-library-hours-extended|Library hours extended>";
+
+    struct Harness {
+        store: Arc<MemoryStore>,
+        trello: Arc<FakeTrello>,
+        slack: FakeSlack,
+        supervisor: Supervisor,
+        handler: CommandHandler
+    }
+
+    impl Harness {
+        // Sends a command as if it came from the user's DM, and returns the replies
+        fn say(&self, user: &str, text: &str) -> Vec<String> {
+            self.mention(user, &format!("D{}", user), text)
+        }
+
+        // Links a Slack user as if they had already proven the Trello account is theirs
+        fn link(&self, user: &str, trello: &str) {
+            Links::new(self.store.clone()).confirm(user, trello).unwrap();
+        }
+
+        fn link_code(&self, user: &str) -> String {
+            Links::new(self.store.clone()).pending(user).unwrap().unwrap().code
+        }
+
+        // Sends a command in the given channel, and returns the replies, which all have to go back to it
+        fn mention(&self, user: &str, channel: &str, text: &str) -> Vec<String> {
+            let message = IncomingMessage {
+                user: user.to_string(),
+                channel: channel.to_string(),
+                text: text.to_string()
+            };
+            self.handler.handle_message(&message, &self.slack).unwrap();
+
+            let replies = self.slack.take_messages();
+            assert!(replies.iter().all(|reply| reply.channel == channel));
+            replies.into_iter().map(|reply| reply.text).collect()
+        }
+    }
+
+    fn setup() -> Harness {
+        let store = Arc::new(MemoryStore::new());
+
+        let trello = Arc::new(FakeTrello::new());
+        trello.respond_with_fixture(Method::Get, &format!("/boards/{}/memberships", BOARD_ID), "memberships.json");
+        trello.respond_with_fixture(Method::Get, &format!("/boards/{}/lists", BOARD_ID), "lists.json");
+        trello.respond_with_fixture(Method::Get, &format!("/boards/{}/cards", BOARD_ID), "board_cards.json");
+        trello.respond_with_fixture(Method::Get, &format!("/cards/{}", CARD_ID), "card.json");
+        trello.respond_with_fixture(Method::Get, "/cards/CrD1sHrt", "card.json");
+        trello.respond_with_fixture(Method::Get, &format!("/cards/{}/actions", CARD_ID), "card_actions.json");
+        trello.respond_with_fixture(Method::Get, &format!("/cards/{}/checklists", CARD_ID), "checklists.json");
+        trello.respond_with_fixture(Method::Get, &format!("/cards/{}/attachments", CARD_ID), "attachments.json");
+        trello.respond_with_fixture(Method::Get, "/lists/5a2000000000000000000002", "list.json");
+        trello.respond_with_fixture(Method::Get, "/lists/5a2000000000000000000002/cards", "list_cards.json");
+        trello.respond(Method::Get, "/lists/5a2000000000000000000003/cards", "[]");
+
+        // Commands don't deliver notifications themselves, so the sinks are only there to check addresses against
+        let (tx, _) = mpsc::sync_channel(NOTIFICATION_BUFFER_SIZE);
+        let mut sinks = NotificationSinks::new();
+        sinks.add(Box::new(SlackSink::new(NotificationSender::new(tx, Arc::new(AtomicUsize::new(0))))));
+        sinks.add(Box::new(EmailSink::new("smtp.example.com", None, "articlebot@example.com", Templates::defaults())));
+        sinks.add(Box::new(WebhookSink::discord(Templates::defaults())));
+
+        let client : Arc<TrelloApi> = trello.clone();
+        let supervisor = Supervisor::new();
+        let handler = CommandHandler::new(
+            store.clone(),
+            Analytics::new(EventStore::new(store.clone()), "Published", 14),
+            supervisor.handle(),
+            BoardHandler::new(BOARD_ID, Arc::clone(&client)),
+            CardHandler::new(client),
+            sinks,
+            Templates::defaults());
+
+        Harness {
+            store: store,
+            trello: trello,
+            slack: FakeSlack::new(),
+            supervisor: supervisor,
+            handler: handler
+        }
+    }
+
+    // A comment on the board from the given Trello user, made just now
+    fn comment_by(username: &str, text: &str) -> String {
+        let mut comment = serde_json::from_str::<Vec<serde_json::Value>>(&fixture("board_actions.json")).unwrap().remove(0);
+        comment["memberCreator"]["username"] = json!(username);
+        comment["data"]["text"] = json!(text);
+        comment["date"] = json!(Utc::now().to_rfc3339());
+        json!([comment]).to_string()
+    }
+
+    fn record_move(harness: &Harness) {
+        let actions : Vec<Action> = serde_json::from_str(&fixture("board_actions.json")).unwrap();
+        let mut event = CardEvent::from_action(&actions[1]);
+        event.recipients = vec!["U1".to_string()];
+        EventStore::new(harness.store.clone()).record(&event).unwrap();
+    }
+
+    #[test]
+    fn greetings_and_version() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "hello"), vec!["Hello there."]);
+        assert_eq!(harness.say("U1", "HI"), vec!["Hello there."]);
+        assert_eq!(harness.say("U1", "version"), vec![format!("Running v{}.", env!("CARGO_PKG_VERSION"))]);
+    }
+
+    #[test]
+    fn status_reports_workers() {
+        let harness = setup();
+        harness.supervisor.spawn("trello-poller", || thread::sleep(time::Duration::from_secs(3600)));
+
+        let replies = harness.say("U1", "status");
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].starts_with("*trello-poller* is running (started "));
+        assert!(replies[0].ends_with(", restarted 0 times)."));
+    }
+
+    #[test]
+    fn help_mentions_every_command() {
+        let harness = setup();
+        let tutorial = harness.say("U1", "tutorial");
+        assert_eq!(tutorial.len(), 6);
+        assert_eq!(tutorial[5], "Please type in `help` for a list of commands.");
+
+        let help = harness.say("U1", "help").join("\n");
+        for command in ["hello", "version", "status", "tutorial", "help", "tracking", "track", "untrack", "link", "unlink", "move", "assign",
+                        "comment", "cards", "list", "card", "history", "export", "stats", "snooze", "reminders", "mute", "unmute",
+                        "subscribe", "unsubscribe", "subscriptions", "language", "admin"].iter() {
+            assert!(help.contains(&format!("`{}", command)), "help does not mention {}", command);
+        }
+    }
+
+    #[test]
+    fn track_and_untrack() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "tracking"), vec!["You are currently not tracking a Trello user."]);
+        assert_eq!(harness.say("U1", "untrack"), vec!["You are currently not tracking a Trello user."]);
+
+        assert_eq!(harness.say("U1", "track wendywriter"), vec!["You will now be notified when *wendywriter*'s articles are moved in Trello."]);
+        assert_eq!(harness.say("U2", "track wendywriter").len(), 1);
+        assert_eq!(harness.say("U1", "tracking"), vec!["You are currently tracking *wendywriter* on Trello."]);
+        assert_eq!(harness.store.all("slack")[0].get_str("cid").unwrap(), "DU1");
+        assert_eq!(harness.store.all("trello")[0].get_array("trackers").unwrap(), &vec![Bson::String("U1".to_string()), Bson::String("U2".to_string())]);
+
+        assert_eq!(harness.say("U1", "untrack"), vec!["You are no longer tracking anyone in Trello."]);
+        assert_eq!(harness.say("U2", "untrack").len(), 1);
+        assert!(harness.store.all("slack").is_empty());
+        assert!(harness.store.all("trello").is_empty());
+    }
+
+    #[test]
+    fn tracking_from_a_channel_notifies_in_a_dm() {
+        let harness = setup();
+        assert_eq!(harness.mention("U1", "C1", "track wendywriter"), vec!["You will now be notified when *wendywriter*'s articles are moved in Trello."]);
+        assert_eq!(harness.store.all("slack")[0].get_str("cid").unwrap(), "DU1");
+    }
+
+    #[test]
+    fn untrack_button_only_untracks_its_writer() {
+        let harness = setup();
+        harness.say("U1", "track wendywriter");
+        harness.say("U1", "track fredfreelancer");
+
+        // A button from an older notification names the previous writer
+        assert_eq!(harness.say("U1", "untrack wendywriter"), vec!["You are no longer tracking *wendywriter*, so nothing was changed."]);
+        assert_eq!(harness.say("U1", "tracking"), vec!["You are currently tracking *fredfreelancer* on Trello."]);
+
+        assert_eq!(harness.say("U1", "untrack fredfreelancer"), vec!["You are no longer tracking anyone in Trello."]);
+        assert!(harness.store.all("slack").is_empty());
+        assert!(harness.store.all("trello").is_empty());
+    }
+
+    #[test]
+    fn muted_cards_carry_over_to_the_next_writer() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "mute CrD1sHrt"), vec!["You are currently not tracking a Trello user."]);
+        assert_eq!(harness.say("U1", "mute"), vec!["Please specify a card, e.g. `mute [CARD]`."]);
+
+        harness.say("U1", "track wendywriter");
+        assert_eq!(harness.say("U1", "mute CrD1sHrt"), vec!["You will no longer be notified when card `CrD1sHrt` is moved."]);
+        harness.say("U1", "track eddieeditor");
+
+        let slack = harness.store.all("slack");
+        assert_eq!(slack.len(), 1);
+        assert_eq!(slack[0].get_array("muted").unwrap(), &vec![Bson::String("CrD1sHrt".to_string())]);
+        let trello = harness.store.all("trello");
+        assert_eq!(trello.len(), 1);
+        assert_eq!(trello[0].get_str("name").unwrap(), "eddieeditor");
+
+        assert_eq!(harness.say("U1", "unmute CrD1sHrt"), vec!["You will once again be notified when card `CrD1sHrt` is moved."]);
+        assert!(harness.store.all("slack")[0].get_array("muted").unwrap().is_empty());
+    }
+
+    #[test]
+    fn link_needs_a_code_commented_from_the_trello_account() {
+        let harness = setup();
+        let comments = format!("/boards/{}/actions", BOARD_ID);
+        assert_eq!(harness.say("U1", "link confirm"), vec!["You have no link waiting to be confirmed. Start one with `link [USERNAME]`."]);
+        assert_eq!(harness.say("U1", "link nobody"), vec!["*nobody* is not a member of the Trello board."]);
+
+        let replies = harness.say("U1", "link @WendyWriter");
+        let code = harness.link_code("U1");
+        assert_eq!(replies, vec![format!("To prove that *wendywriter* is your Trello account, comment `{}` on any card on the board while logged in as *wendywriter*, \
+            then send `link confirm`. The code works for a day, and the comment can be deleted afterwards.", code)]);
+        assert!(harness.store.all("links").is_empty());
+
+        // Nobody else can post the code on the account's behalf
+        harness.trello.respond(Method::Get, &comments, "[]");
+        harness.trello.respond(Method::Get, &comments, &comment_by("eddieeditor", &format!("Linking {}", code)));
+        harness.trello.respond(Method::Get, &comments, &comment_by("wendywriter", &format!("Linking {}", code)));
+        let not_found = format!("I could not find a comment with `{}` from *wendywriter* on the board yet. Comment it on any card, then send `link confirm` again.", code);
+        assert_eq!(harness.say("U1", "link confirm"), vec![not_found.clone()]);
+        assert_eq!(harness.say("U1", "link confirm"), vec![not_found]);
+        assert!(harness.store.all("links").is_empty());
+
+        assert_eq!(harness.say("U1", "link confirm"), vec!["Your Slack account is now linked to *wendywriter* on Trello."]);
+        let links = harness.store.all("links");
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].get_str("trello").unwrap(), "wendywriter");
+        assert!(harness.store.all("link_requests").is_empty());
+
+        assert_eq!(harness.say("U1", "unlink"), vec!["Your Slack account is no longer linked to a Trello account."]);
+        assert_eq!(harness.say("U1", "unlink"), vec!["Your Slack account is not linked to a Trello account."]);
+    }
+
+    #[test]
+    fn link_defaults_to_the_slack_username() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "link"), vec!["Please specify your Trello username, e.g. `link johndoe42`."]);
+
+        harness.slack.add_user("U1", "wendywriter");
+        assert!(harness.say("U1", "link")[0].starts_with("To prove that *wendywriter* is your Trello account"));
+        assert_eq!(harness.store.all("link_requests")[0].get_str("trello").unwrap(), "wendywriter");
+    }
+
+    #[test]
+    fn move_is_limited_to_card_members_and_admins() {
+        let harness = setup();
+        harness.trello.respond_with_fixture(Method::Put, &format!("/cards/{}", CARD_ID), "card.json");
+
+        assert_eq!(harness.say("U1", "move CrD1sHrt Drafting"), vec!["Please specify a card and a list, e.g. `move abc123 to Copy Editing`."]);
+        assert_eq!(harness.say("U1", "move nope to Drafting"), vec!["I could not find a card `nope` on the board."]);
+        assert_eq!(harness.say("U1", "move CrD1sHrt to Drafting"), vec!["Please link your Trello account first with `link [USERNAME]`."]);
+
+        // Links made before they had to be proven don't count
+        harness.store.insert_one("links", doc! { "uid": "U1", "trello": "eddieeditor" }).unwrap();
+        assert_eq!(harness.say("U1", "move CrD1sHrt to Drafting"), vec!["Please link your Trello account first with `link [USERNAME]`."]);
+
+        harness.link("U3", "fredfreelancer");
+        assert_eq!(harness.say("U3", "move CrD1sHrt to Drafting"), vec!["Only members of _Library hours extended_ and board admins can change it."]);
+
+        harness.link("U1", "wendywriter");
+        assert_eq!(harness.say("U1", "move CrD1sHrt to copy editing"), vec!["_Library hours extended_ is already in *Copy Editing*."]);
+        assert_eq!(harness.say("U1", "move CrD1sHrt to The Spike"), vec!["I could not find a list called *The Spike* on the board."]);
+        assert!(harness.trello.requests_to(Method::Put, &format!("/cards/{}", CARD_ID)).is_empty());
+
+        harness.link("U2", "eddieeditor");
+        assert_eq!(harness.say("U2", "move https://trello.com/c/CrD1sHrt/1-library-hours-extended to drafting"), vec!["Moved _Library hours extended_ to *Drafting*."]);
+        assert_eq!(harness.trello.requests_to(Method::Put, &format!("/cards/{}", CARD_ID)), vec![format!("PUT /cards/{}?idList=5a2000000000000000000001", CARD_ID)]);
+    }
+
+    #[test]
+    fn assign_adds_board_members() {
+        let harness = setup();
+        harness.trello.respond(Method::Post, &format!("/cards/{}/idMembers", CARD_ID), "[]");
+        harness.link("U2", "eddieeditor");
+
+        assert_eq!(harness.say("U2", "assign CrD1sHrt"), vec!["Please specify a card and a Trello user, e.g. `assign abc123 johndoe42`."]);
+        assert_eq!(harness.say("U2", "assign CrD1sHrt nobody"), vec!["*nobody* is not a member of the Trello board."]);
+        assert_eq!(harness.say("U2", "assign CrD1sHrt wendywriter"), vec!["*wendywriter* is already on _Library hours extended_."]);
+        assert_eq!(harness.say("U2", "assign CrD1sHrt fredfreelancer"), vec!["Assigned *fredfreelancer* to _Library hours extended_."]);
+        assert_eq!(harness.trello.requests_to(Method::Post, &format!("/cards/{}/idMembers", CARD_ID)), vec![format!("POST /cards/{}/idMembers?value=5a1000000000000000000003", CARD_ID)]);
+    }
+
+    #[test]
+    fn comment_credits_the_linked_account() {
+        let harness = setup();
+        harness.trello.respond(Method::Post, &format!("/cards/{}/actions/comments", CARD_ID), "{}");
+        harness.link("U1", "wendywriter");
+
+        assert_eq!(harness.say("U1", "comment CrD1sHrt"), vec!["Please specify a card and a comment, e.g. `comment abc123 Ready for copy.`"]);
+        assert_eq!(harness.say("U1", "comment CrD1sHrt Ready for copy."), vec!["Commented on _Library hours extended_."]);
+        assert_eq!(harness.trello.requests_to(Method::Post, &format!("/cards/{}/actions/comments", CARD_ID)),
+            vec![format!("POST /cards/{}/actions/comments?text=%40wendywriter+%28via+Slack%29%3A+Ready+for+copy.", CARD_ID)]);
+    }
+
+    #[test]
+    fn cards_lists_a_members_cards() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "cards"), vec!["Please specify a Trello user, e.g. `cards johndoe42`."]);
+        assert_eq!(harness.say("U1", "cards nobody"), vec!["*nobody* is not a member of the Trello board."]);
+        assert_eq!(harness.say("U1", "cards fredfreelancer"), vec!["*fredfreelancer* has no open cards."]);
+        assert_eq!(harness.say("U1", "cards wendywriter"), vec![format!("*wendywriter* has 1 open cards:\n• {} in *Copy Editing*", CARD_LINK)]);
+    }
+
+    #[test]
+    fn list_shows_cards_and_their_members() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "list"), vec!["Please specify a list, e.g. `list Copy Editing`."]);
+        assert_eq!(harness.say("U1", "list The Spike"), vec!["I could not find a list called *The Spike* on the board."]);
+        assert_eq!(harness.say("U1", "list published"), vec!["*Published* is empty."]);
+        assert_eq!(harness.say("U1", "list copy editing"), vec![format!("*Copy Editing* has 1 cards:\n• {} (wendywriter)", CARD_LINK)]);
+    }
+
+    #[test]
+    fn card_shows_details_and_history() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "card"), vec!["Please specify a card, e.g. `card abc123`."]);
+        assert_eq!(harness.say("U1", "card nope"), vec!["I could not find a card `nope` on the board."]);
+        assert_eq!(harness.say("U1", "card CrD1sHrt"), vec![format!("*{}*\n\
+            List: *Copy Editing*\n\
+            Members: wendywriter, eddieeditor\n\
+            Labels: News\n\
+            Due: Jun 5, 2018 17:00 UTC\n\
+            Checklist _Before publishing_: 1/2 done\n\
+            Attachments: 1\n\
+            History:\n\
+            • May 28, 2018 09:00 UTC: created in *Drafting* by eddieeditor\n\
+            • Jun 1, 2018 12:15 UTC: moved from *Drafting* to *Copy Editing* by eddieeditor", CARD_LINK)]);
+    }
+
+    #[test]
+    fn history_and_export_use_recorded_events() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "history"), vec!["Please specify a card, e.g. `history abc123`."]);
+        assert_eq!(harness.say("U1", "history CrD1sHrt"), vec!["I have not seen anything happen to card `CrD1sHrt`."]);
+        assert_eq!(harness.say("U1", "export CrD1sHrt"), vec!["There is nothing to export."]);
+
+        record_move(&harness);
+        assert_eq!(harness.say("U1", "history CrD1sHrt"), vec!["History of _Library hours extended_:\n\
+            • Jun 1, 2018 12:15 UTC: moved from *Drafting* to *Copy Editing* by eddieeditor (notified <@U1>)"]);
+        assert_eq!(harness.say("U1", "export CrD1sHrt"), vec!["```date,card,short_link,name,type,from,to,actor,recipients\n\
+            2018-06-01T12:15:00+00:00,5a3000000000000000000001,CrD1sHrt,Library hours extended,updateCard,Drafting,Copy Editing,eddieeditor,U1```"]);
+        // A bare export only covers the past week
+        assert_eq!(harness.say("U1", "export"), vec!["There is nothing to export."]);
+    }
+
+    #[test]
+    fn stats_reports_stalled_cards() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "stats 0"), vec!["Please specify a number of days, e.g. `stats 30`."]);
+
+        record_move(&harness);
+        assert_eq!(harness.say("U1", "stats"), vec!["*Pipeline report for the last 30 days*\n\
+            Time spent in each list (median / 90th percentile):\n\
+            • No cards have left a list yet.\n\
+            Stories published per writer:\n\
+            • Nothing has been published.\n\
+            Stalled cards (not moved in 14 days):\n\
+            • _Library hours extended_ in *Copy Editing* since Jun 1"]);
+    }
+
+    #[test]
+    fn stats_count_stays_that_began_before_the_window() {
+        let harness = setup();
+        let actions : Vec<Action> = serde_json::from_str(&fixture("board_actions.json")).unwrap();
+        let events = EventStore::new(harness.store.clone());
+        let now = Utc::now();
+        for &(action_id, from, to, days_ago) in [("a1", "Pitches", "Drafting", 40), ("a2", "Drafting", "Published", 2)].iter() {
+            let mut event = CardEvent::from_action(&actions[1]);
+            event.action_id = action_id.to_string();
+            event.from_list = Some(from.to_string());
+            event.to_list = Some(to.to_string());
+            event.date = now - Duration::days(days_ago);
+            event.members = vec!["wendywriter".to_string()];
+            events.record(&event).unwrap();
+        }
+
+        assert_eq!(harness.say("U1", "stats 7"), vec!["*Pipeline report for the last 7 days*\n\
+            Time spent in each list (median / 90th percentile):\n\
+            • *Drafting*: 38d 0h / 38d 0h (1 cards)\n\
+            Stories published per writer:\n\
+            • wendywriter: 1\n\
+            Stalled cards (not moved in 14 days):\n\
+            • None."]);
+    }
+
+    #[test]
+    fn snooze_records_the_card() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "snooze"), vec!["Please specify a card, e.g. `snooze abc123 7`."]);
+        assert_eq!(harness.say("U1", "snooze CrD1sHrt soon"), vec!["Please specify a number of days, e.g. `snooze abc123 7`."]);
+        assert_eq!(harness.say("U1", "snooze nope"), vec!["I could not find a card `nope` on the board."]);
+
+        let replies = harness.say("U1", "snooze CrD1sHrt 3");
+        assert_eq!(replies, vec![format!("You will not be reminded about _Library hours extended_ until {}.", (Utc::now() + Duration::days(3)).format("%b %-d"))]);
+        assert!(ReminderPreferences::new(harness.store.clone()).is_snoozed("U1", CARD_ID).unwrap());
+        assert!(!ReminderPreferences::new(harness.store.clone()).is_snoozed("U2", CARD_ID).unwrap());
+    }
+
+    #[test]
+    fn reminders_can_be_turned_off_and_on() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "reminders"), vec!["Reminders are currently *on*. Use `reminders on` or `reminders off` to change this."]);
+        assert_eq!(harness.say("U1", "reminders OFF"), vec!["You will no longer be reminded about stale cards and due dates."]);
+        assert_eq!(harness.say("U1", "reminders"), vec!["Reminders are currently *off*. Use `reminders on` or `reminders off` to change this."]);
+        assert_eq!(harness.say("U1", "reminders on"), vec!["You will be reminded about stale cards and due dates."]);
+        assert!(harness.store.all("reminder_opt_outs").is_empty());
+    }
+
+    #[test]
+    fn subscribe_checks_the_sink_and_address() {
+        let harness = setup();
+        let not_allowed = |username: &str| vec![format!("You can only subscribe to notifications about your own cards, \
+            so link your Trello account first if *{}* is you.", username)];
+        assert_eq!(harness.say("U1", "subscribe fredfreelancer email fred@example.com"), not_allowed("fredfreelancer"));
+
+        harness.link("U1", "fredfreelancer");
+        assert_eq!(harness.say("U1", "subscribe fredfreelancer pigeon fred@example.com"), vec!["There is no sink called `pigeon`. Try one of slack, email, discord."]);
+        assert_eq!(harness.say("U1", "subscribe fredfreelancer discord fred@example.com"), vec!["fred@example.com is not a Discord webhook URL."]);
+        assert_eq!(harness.say("U1", "subscribe fredfreelancer email <mailto:fred@example.com|fred@example.com>"),
+            vec!["Notifications about *fredfreelancer*'s cards will now also go to fred@example.com (email)."]);
+        assert_eq!(harness.say("U1", "subscribe fredfreelancer email fred@example.com"), vec!["Notifications about *fredfreelancer*'s cards already go to fred@example.com."]);
+        assert_eq!(harness.say("U1", "subscribe wendywriter slack <#C024BE91L|newsroom>"), not_allowed("wendywriter"));
+
+        // Admins can send anyone's notifications on
+        Admins::new(harness.store.clone()).grant("U2").unwrap();
+        assert_eq!(harness.say("U2", "subscribe wendywriter slack <#C024BE91L|newsroom>"),
+            vec!["Notifications about *wendywriter*'s cards will now also go to C024BE91L (slack)."]);
+
+        assert_eq!(harness.store.all("subscriptions").len(), 2);
+        assert_eq!(harness.store.all("subscriptions")[0].get_str("creator").unwrap(), "U1");
+    }
+
+    #[test]
+    fn subscriptions_are_listed_and_removed() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "subscriptions"), vec!["You have no subscriptions, and none go to your own Trello account's cards."]);
+
+        harness.link("U1", "wendywriter");
+        harness.link("U2", "fredfreelancer");
+        harness.say("U1", "subscribe wendywriter discord https://discord.com/api/webhooks/1/abc");
+        harness.say("U2", "subscribe fredfreelancer email fred@example.com");
+
+        // Everyone but admins only sees their own subscriptions, since the targets can't be shared around
+        assert_eq!(harness.say("U1", "subscriptions"), vec!["• *wendywriter* → https://discord.com/api/webhooks/1/abc (discord, set up by <@U1>)"]);
+        assert_eq!(harness.say("U1", "subscriptions fredfreelancer"),
+            vec!["You have no subscriptions, and none go to your own Trello account's cards."]);
+        assert_eq!(harness.say("U2", "subscriptions fredfreelancer"), vec!["• *fredfreelancer* → fred@example.com (email, set up by <@U2>)"]);
+        Admins::new(harness.store.clone()).grant("U4").unwrap();
+        assert_eq!(harness.say("U4", "subscriptions"), vec![
+            "• *fredfreelancer* → fred@example.com (email, set up by <@U2>)\n• *wendywriter* → https://discord.com/api/webhooks/1/abc (discord, set up by <@U1>)"
+        ]);
+
+        // Only whoever set a subscription up, or an admin, can remove it
+        assert_eq!(harness.say("U1", "unsubscribe fredfreelancer fred@example.com"),
+            vec!["Notifications about *fredfreelancer*'s cards go to fred@example.com, but only whoever set that up or an admin can stop them."]);
+        assert_eq!(harness.say("U2", "unsubscribe fredfreelancer fred@example.com"), vec!["Notifications about *fredfreelancer*'s cards will no longer go to fred@example.com."]);
+        assert_eq!(harness.say("U2", "unsubscribe fredfreelancer fred@example.com"), vec!["Notifications about *fredfreelancer*'s cards do not go to fred@example.com."]);
+        assert_eq!(harness.store.all("subscriptions").len(), 1);
+
+        Admins::new(harness.store.clone()).grant("U3").unwrap();
+        assert_eq!(harness.say("U3", "unsubscribe wendywriter https://discord.com/api/webhooks/1/abc").len(), 1);
+        assert!(harness.store.all("subscriptions").is_empty());
+    }
+
+    #[test]
+    fn replies_are_in_the_users_language() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "language"), vec!["I am talking to you in *en*. Use `language [LANGUAGE]` to change this, where [LANGUAGE] is one of en, es."]);
+        assert_eq!(harness.say("U1", "language klingon"), vec!["I do not speak `klingon`. Try one of en, es."]);
+        assert_eq!(harness.say("U1", "language ES"), vec!["A partir de ahora te hablaré en español."]);
+
+        assert_eq!(harness.say("U1", "hello"), vec!["Hola."]);
+        assert_eq!(harness.say("U2", "hello"), vec!["Hello there."]);
+
+        harness.link("U1", "fredfreelancer");
+        assert_eq!(harness.say("U1", "subscribe fredfreelancer discord fred@example.com"), vec!["fred@example.com no es una URL de webhook de Discord."]);
+    }
+
+    #[test]
+    fn admin_commands_are_limited_to_admins() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "admin track <@U2> wendywriter"), vec!["Only admins can use `admin` commands."]);
+        assert!(harness.store.all("slack").is_empty());
+
+        Admins::new(harness.store.clone()).grant("U1").unwrap();
+        assert_eq!(harness.say("U1", "admin grant <@U2|eddie>"), vec!["<@U2> is now an admin."]);
+        assert_eq!(harness.say("U2", "admin revoke <@U1>"), vec!["<@U1> is no longer an admin."]);
+        assert_eq!(harness.say("U1", "admin"), vec!["Only admins can use `admin` commands."]);
+        assert_eq!(harness.say("U2", "admin revoke <@U2>"), vec!["You cannot revoke your own admin role."]);
+    }
+
+    #[test]
+    fn admins_manage_other_users_tracking() {
+        let harness = setup();
+        Admins::new(harness.store.clone()).grant("U1").unwrap();
+
+        assert_eq!(harness.say("U1", "admin track <@U2> wendywriter"), vec!["<@U2> will now be notified when *wendywriter*'s articles are moved in Trello."]);
+        assert_eq!(harness.slack.opened_dms(), vec!["U2"]);
+        assert_eq!(harness.say("U2", "tracking"), vec!["You are currently tracking *wendywriter* on Trello."]);
+        assert_eq!(harness.store.all("slack")[0].get_str("cid").unwrap(), "DU2");
+
+        harness.link("U3", "fredfreelancer");
+        harness.say("U3", "subscribe fredfreelancer email fred@example.com");
+        assert_eq!(harness.say("U1", "admin subscriptions"), vec![
+            "Slack trackers:\n• <@U2> → *wendywriter*\nSubscriptions:\n• *fredfreelancer* → fred@example.com (email, set up by <@U3>)"
+        ]);
+
+        assert_eq!(harness.say("U1", "admin untrack <@U2>"), vec!["<@U2> is no longer tracking anyone in Trello."]);
+        assert_eq!(harness.say("U1", "admin untrack <@U2>"), vec!["<@U2> is not tracking a Trello user."]);
+        assert!(harness.store.all("trello").is_empty());
+    }
+
+    #[test]
+    fn admins_can_broadcast_and_reload() {
+        let harness = setup();
+        Admins::new(harness.store.clone()).grant("U1").unwrap();
+        harness.say("U2", "track wendywriter");
+        harness.link("U3", "eddieeditor");
+
+        let broadcast = |text: &str| {
+            let message = IncomingMessage {
+                user: "U1".to_string(),
+                channel: "DU1".to_string(),
+                text: text.to_string()
+            };
+            harness.handler.handle_message(&message, &harness.slack).unwrap();
+            let mut messages : Vec<(String, String)> = harness.slack.take_messages().into_iter().map(|message| (message.channel, message.text)).collect();
+            messages.sort();
+            messages
+        };
+        let message = "articlebot will be down tonight.".to_string();
+        assert_eq!(broadcast("admin broadcast articlebot will be down tonight."), vec![
+            ("DU1".to_string(), "Sent your message to 2 users.".to_string()),
+            ("DU2".to_string(), message.clone()),
+            ("DU3".to_string(), message.clone())
+        ]);
+
+        // Someone who can't be reached is reported, and everyone else still gets the message
+        harness.slack.make_unreachable("DU2");
+        assert_eq!(broadcast("admin broadcast articlebot will be down tonight."), vec![
+            ("DU1".to_string(), "Sent your message to 1 users. 1 could not be reached, see the log for why.".to_string()),
+            ("DU3".to_string(), message)
+        ]);
+
+        assert_eq!(harness.say("U1", "admin reload"), vec!["Reloaded the templates."]);
+
+        let help = harness.say("U1", "admin help");
+        assert!(help[0].contains("`admin broadcast [TEXT]`"));
+        assert_eq!(harness.say("U1", "admin publish"), vec!["I did not understand the admin command `publish`.".to_string(), help[0].clone()]);
+    }
+
+    #[test]
+    fn unknown_commands_point_to_help() {
+        let harness = setup();
+        assert_eq!(harness.say("U1", "publish everything"), vec!["I did not understand your command `publish`.", "Try typing in `help` to see a list of available commands."]);
+        assert!(harness.slack.opened_dms().is_empty());
+        assert!(harness.trello.requests().is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use std::error::Error;
+
+use store::Store;
+use trello_models::Action;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bson::{Bson, Document};
+use chrono::prelude::*;
+use chrono::Duration;
+
+const EVENTS_COLLECTION: &'static str = "events";
+// Each card's latest move, so that where every card sits can be read without going through the whole history
+const LATEST_MOVES_COLLECTION: &'static str = "latest_moves";
+
+pub struct CardEvent {
+    pub action_id: String,
+    pub action_type: String,
+    pub card_id: String,
+    pub card_short_link: String,
+    pub card_name: String,
+    pub from_list: Option<String>,
+    pub to_list: Option<String>,
+    pub actor: String,
+    pub date: DateTime<Utc>,
+    // For moves, when the card was moved into the list it is leaving, if articlebot saw that happen
+    pub entered: Option<DateTime<Utc>>,
+    pub members: Vec<String>,
+    pub recipients: Vec<String>
+}
+
+impl CardEvent {
+    pub fn from_action(action: &Action) -> CardEvent {
+        let card = action.data.card();
+        let list_move = action.data.list_move();
+
+        CardEvent {
+            action_id: action.id.clone(),
+            action_type: action.action_type.clone(),
+            card_id: card.map(|card| card.id.clone()).unwrap_or(String::new()),
+            card_short_link: card.map(|card| card.short_link.clone()).unwrap_or(String::new()),
+            card_name: card.map(|card| card.name.clone()).unwrap_or(String::new()),
+            from_list: list_move.and_then(|(before, _)| before.name.clone()),
+            to_list: list_move.and_then(|(_, after)| after.name.clone()),
+            actor: action.creator.username.clone(),
+            date: action.date,
+            entered: None,
+            members: Vec::new(),
+            recipients: Vec::new()
+        }
+    }
+
+    pub fn is_move(&self) -> bool {
+        self.from_list.is_some() && self.to_list.is_some()
+    }
+
+    fn to_document(&self) -> Document {
+        let optional = |value: &Option<String>| value.clone().map(Bson::String).unwrap_or(Bson::Null);
+        let strings = |values: &Vec<String>| Bson::Array(values.iter().map(|value| Bson::String(value.clone())).collect());
+
+        let mut edoc = doc! {
+            "action": &self.action_id,
+            "type": &self.action_type,
+            "card": &self.card_id,
+            "short_link": &self.card_short_link,
+            "name": &self.card_name,
+            "from": optional(&self.from_list),
+            "to": optional(&self.to_list),
+            "actor": &self.actor,
+            "date": Bson::UtcDatetime(self.date),
+            "members": strings(&self.members),
+            "recipients": strings(&self.recipients)
+        };
+        // Left out rather than nulled, so recording the same action again doesn't lose it
+        if let Some(entered) = self.entered {
+            edoc.insert("entered", Bson::UtcDatetime(entered));
+        }
+        edoc
+    }
+
+    fn from_document(edoc: &Document) -> CardEvent {
+        let string = |key: &str| edoc.get_str(key).unwrap_or("").to_string();
+        let optional = |key: &str| edoc.get_str(key).ok().map(|value| value.to_string());
+        let strings = |key: &str| edoc.get_array(key)
+            .map(|values| values.iter().filter_map(|value| value.as_str()).map(|value| value.to_string()).collect())
+            .unwrap_or(Vec::new());
+
+        CardEvent {
+            action_id: string("action"),
+            action_type: string("type"),
+            card_id: string("card"),
+            card_short_link: string("short_link"),
+            card_name: string("name"),
+            from_list: optional("from"),
+            to_list: optional("to"),
+            actor: string("actor"),
+            date: edoc.get_utc_datetime("date").map(|date| *date).unwrap_or(Utc::now()),
+            entered: edoc.get_utc_datetime("entered").ok().map(|date| *date),
+            members: strings("members"),
+            recipients: strings("recipients")
+        }
+    }
+
+    pub fn csv_header() -> &'static str {
+        "date,card,short_link,name,type,from,to,actor,recipients"
+    }
+
+    pub fn to_csv(&self) -> String {
+        let escape = |value: &str| {
+            if value.contains(',') || value.contains('"') || value.contains('\n') {
+                format!("\"{}\"", value.replace("\"", "\"\""))
+            }
+            else {
+                value.to_string()
+            }
+        };
+
+        vec![
+            self.date.to_rfc3339(),
+            escape(&self.card_id),
+            escape(&self.card_short_link),
+            escape(&self.card_name),
+            escape(&self.action_type),
+            escape(self.from_list.as_ref().map(|list| &list[..]).unwrap_or("")),
+            escape(self.to_list.as_ref().map(|list| &list[..]).unwrap_or("")),
+            escape(&self.actor),
+            escape(&self.recipients.join(" "))
+        ].join(",")
+    }
+}
+
+pub struct EventStore {
+    store: Arc<Store>
+}
+
+impl EventStore {
+    pub fn new(store: Arc<Store>) -> EventStore {
+        EventStore {
+            store: store
+        }
+    }
+
+    pub fn record(&self, event: &CardEvent) -> Result<(), Box<Error>> {
+        let mut edoc = event.to_document();
+
+        if event.is_move() {
+            let latest = self.store.find_one(LATEST_MOVES_COLLECTION, doc! {
+                "card": &event.card_id
+            })?.map(|ldoc| CardEvent::from_document(&ldoc));
+
+            // The card's previous move is when it entered the list it is now leaving
+            if let Some(ref latest) = latest {
+                if latest.action_id != event.action_id && latest.date <= event.date && latest.to_list == event.from_list {
+                    edoc.insert("entered", Bson::UtcDatetime(latest.date));
+                }
+            }
+
+            if latest.map(|latest| latest.date <= event.date).unwrap_or(true) {
+                self.store.update_one(LATEST_MOVES_COLLECTION, doc! {
+                    "card": &event.card_id
+                }, doc! {
+                    "$set": edoc.clone()
+                }, true)?;
+            }
+        }
+
+        // Keyed by the Trello action, so polling the same action twice doesn't duplicate it
+        self.store.update_one(EVENTS_COLLECTION, doc! {
+            "action": &event.action_id
+        }, doc! {
+            "$set": edoc
+        }, true)?;
+
+        Ok(())
+    }
+
+    // Fills in the latest moves from the full history, for events recorded before they were kept
+    pub fn index_latest_moves(&self) -> Result<(), Box<Error>> {
+        if self.store.find_one(LATEST_MOVES_COLLECTION, doc! {})?.is_some() {
+            return Ok(());
+        }
+
+        let mut latest : HashMap<String, Document> = HashMap::new();
+        for mut edoc in self.store.find(EVENTS_COLLECTION, doc! {}, Some(doc! { "date": 1 }))? {
+            edoc.remove("_id");
+            if CardEvent::from_document(&edoc).is_move() {
+                latest.insert(edoc.get_str("card").unwrap_or("").to_string(), edoc);
+            }
+        }
+        for (card_id, edoc) in latest.into_iter() {
+            self.store.update_one(LATEST_MOVES_COLLECTION, doc! {
+                "card": card_id
+            }, doc! {
+                "$set": edoc
+            }, true)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_recipients(&self, action_id: &str, recipients: &[String]) -> Result<(), Box<Error>> {
+        let recipients : Vec<Bson> = recipients.iter().map(|recipient| Bson::String(recipient.clone())).collect();
+        self.store.update_one(EVENTS_COLLECTION, doc! {
+            "action": action_id
+        }, doc! {
+            "$set": { "recipients": Bson::Array(recipients) }
+        }, false)?;
+
+        Ok(())
+    }
+
+    fn find(&self, filter: Document) -> Result<Vec<CardEvent>, Box<Error>> {
+        let edocs = self.store.find(EVENTS_COLLECTION, filter, Some(doc! {
+            "date": 1
+        }))?;
+        Ok(edocs.iter().map(CardEvent::from_document).collect())
+    }
+
+    pub fn card_history(&self, card_ref: &str) -> Result<Vec<CardEvent>, Box<Error>> {
+        self.find(doc! {
+            "$or": [
+                { "card": card_ref },
+                { "short_link": card_ref }
+            ]
+        })
+    }
+
+    pub fn latest_moves(&self) -> Result<Vec<CardEvent>, Box<Error>> {
+        let ldocs = self.store.find(LATEST_MOVES_COLLECTION, doc! {}, None)?;
+        Ok(ldocs.iter().map(CardEvent::from_document).collect())
+    }
+
+    pub fn since(&self, days: i64) -> Result<Vec<CardEvent>, Box<Error>> {
+        self.find(doc! {
+            "date": { "$gte": Bson::UtcDatetime(Utc::now() - Duration::days(days)) }
+        })
+    }
+}
@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use store::Store;
+
+use bson::Bson;
+use chrono::Duration;
+use chrono::prelude::*;
+use rand::{self, Rng};
+
+const LINKS_COLLECTION: &'static str = "links";
+const LINK_REQUESTS_COLLECTION: &'static str = "link_requests";
+const CODE_LENGTH: usize = 8;
+const CODE_HOURS: i64 = 24;
+
+// A link that has been asked for but not proven yet
+pub struct LinkRequest {
+    pub trello: String,
+    pub code: String,
+    pub requested: DateTime<Utc>
+}
+
+// Links between Slack users and their Trello accounts. A link only counts once the Slack user has shown
+// that they own the Trello account, by commenting a one-time code on the board from it.
+pub struct Links {
+    store: Arc<Store>
+}
+
+impl Links {
+    pub fn new(store: Arc<Store>) -> Links {
+        Links {
+            store: store
+        }
+    }
+
+    // Replaces any earlier request, and returns the code that has to be commented from the Trello account
+    pub fn request(&self, uid: &str, trello: &str) -> Result<String, Box<Error>> {
+        let code : String = rand::thread_rng().gen_ascii_chars().take(CODE_LENGTH).collect();
+        self.store.delete_many(LINK_REQUESTS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        self.store.insert_one(LINK_REQUESTS_COLLECTION, doc! {
+            "uid": uid,
+            "trello": trello,
+            "code": &code,
+            "requested": Bson::UtcDatetime(Utc::now())
+        })?;
+        Ok(code)
+    }
+
+    // Requests expire after a day, so an old code lying around in a comment can't be used later on
+    pub fn pending(&self, uid: &str) -> Result<Option<LinkRequest>, Box<Error>> {
+        let rdoc = match self.store.find_one(LINK_REQUESTS_COLLECTION, doc! {
+            "uid": uid
+        })? {
+            Some(rdoc) => rdoc,
+            None => return Ok(None)
+        };
+        let requested = *rdoc.get_utc_datetime("requested")?;
+        if requested + Duration::hours(CODE_HOURS) < Utc::now() {
+            self.store.delete_many(LINK_REQUESTS_COLLECTION, doc! {
+                "uid": uid
+            })?;
+            return Ok(None);
+        }
+
+        Ok(Some(LinkRequest {
+            trello: rdoc.get_str("trello")?.to_string(),
+            code: rdoc.get_str("code")?.to_string(),
+            requested: requested
+        }))
+    }
+
+    // Called once the code has been found, and makes the link count
+    pub fn confirm(&self, uid: &str, trello: &str) -> Result<(), Box<Error>> {
+        self.store.delete_many(LINK_REQUESTS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        self.store.delete_many(LINKS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        self.store.insert_one(LINKS_COLLECTION, doc! {
+            "uid": uid,
+            "trello": trello,
+            "verified": true
+        })?;
+        Ok(())
+    }
+
+    // Returns false if there was neither a link nor a request to remove
+    pub fn unlink(&self, uid: &str) -> Result<bool, Box<Error>> {
+        let requests = self.store.delete_many(LINK_REQUESTS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        let links = self.store.delete_many(LINKS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        Ok(requests + links > 0)
+    }
+
+    // The Trello account a Slack user has proven is theirs. Links made before codes were needed don't count.
+    pub fn trello_account(&self, uid: &str) -> Result<Option<String>, Box<Error>> {
+        let ldoc = self.store.find_one(LINKS_COLLECTION, doc! {
+            "uid": uid,
+            "verified": true
+        })?;
+        Ok(ldoc.map(|ldoc| ldoc.get_str("trello").unwrap().to_string()))
+    }
+
+    pub fn slack_users(&self, trello: &str) -> Result<Vec<String>, Box<Error>> {
+        let ldocs = self.store.find(LINKS_COLLECTION, doc! {
+            "trello": trello,
+            "verified": true
+        }, None)?;
+        Ok(ldocs.iter().map(|ldoc| ldoc.get_str("uid").unwrap().to_string()).collect())
+    }
+
+    pub fn all_slack_users(&self) -> Result<Vec<String>, Box<Error>> {
+        let ldocs = self.store.find(LINKS_COLLECTION, doc! {
+            "verified": true
+        }, None)?;
+        Ok(ldocs.iter().map(|ldoc| ldoc.get_str("uid").unwrap().to_string()).collect())
+    }
+}
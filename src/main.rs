@@ -1,5 +1,4 @@
 extern crate slack;
-extern crate slack_api;
 extern crate slack_hook;
 extern crate reqwest;
 extern crate chrono;
@@ -9,84 +8,123 @@ extern crate env_logger;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 #[macro_use(bson, doc)]
 extern crate bson;
 extern crate mongodb;
+extern crate url;
+extern crate rand;
+extern crate tiny_http;
+extern crate hmac;
+extern crate sha2;
+extern crate hex;
+extern crate lettre;
+extern crate lettre_email;
 
+mod cache;
 mod trello;
+mod trello_client;
 mod trello_models;
 mod trello_listeners;
 mod commands;
+mod history;
+mod supervisor;
+mod slack_transport;
+mod slack_rtm;
+mod slack_web;
+mod slack_http;
+mod slack_events;
+mod scheduler;
+mod analytics;
+mod reminders;
+mod sinks;
+mod subscriptions;
+mod templates;
+mod admins;
+mod links;
+mod store;
+#[cfg(test)]
+mod memory_store;
+#[cfg(test)]
+mod trello_fake;
+#[cfg(test)]
+mod slack_fake;
+#[cfg(test)]
+mod mock_board;
+#[cfg(test)]
+mod simulation;
 
 use std::{env, thread};
 use std::time::Duration;
 use std::sync::mpsc;
-use std::sync::mpsc::Receiver;
-use std::sync::{Mutex, Arc};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use trello::{BoardHandler, CardHandler};
-use trello_listeners::RelayActionListener;
+use trello::{BoardHandler, CardHandler, PollState};
+use trello_client::{TrelloApi, TrelloClient, API_URL};
+use trello_listeners::{HistoryListener, RelayActionListener};
+use history::EventStore;
+use analytics::{Analytics, WeeklyReportJob};
+use scheduler::Scheduler;
+use reminders::{Reminder, StaleCardJob, DueDateJob};
 
 use commands::CommandHandler;
+use supervisor::{Supervisor, SupervisorHandle};
+use slack_transport::{SlackTransport, NotificationBuffer, NotificationSender, NOTIFICATION_BUFFER_SIZE};
+use slack_rtm::RtmTransport;
+use slack_http::SlackHttpServer;
+use slack_events::EventsApiTransport;
+use slack_web::WebApiOutput;
+use sinks::{NotificationSinks, SlackSink, EmailSink, WebhookSink};
+use templates::Templates;
+use admins::Admins;
 
-use slack::{Event, EventHandler, RtmClient, Message};
 use slack_hook::{Slack, PayloadBuilder};
 use mongodb::{Client, ThreadedClient};
-use mongodb::db::Database;
+use store::{Store, MongoStore};
 
 const MONGODB_HOSTNAME: &'static str = "localhost";
 const MONGODB_PORT: u16 = 27017;
 const MONGODB_DATABASE: &'static str = "articlebot";
 const TRY_FLUSH_INTERVAL: u64 = 30;
+const DEFAULT_SLACK_TRANSPORT: &'static str = "rtm";
+const DEFAULT_HTTP_ADDRESS: &'static str = "0.0.0.0:3000";
+const DEFAULT_PUBLISHED_LIST: &'static str = "Published";
+const DEFAULT_STALLED_DAYS: i64 = 14;
+const DEFAULT_STALE_DAYS: i64 = 7;
 
-struct SlackHandler {
-    command_handler: CommandHandler,
-    buffer_rx: Receiver<String>
+fn open_database_connection() -> Arc<Store> {
+    let db = Client::connect(MONGODB_HOSTNAME, MONGODB_PORT).expect("MongoDB connection error").db(MONGODB_DATABASE);
+    Arc::new(MongoStore::new(db))
 }
 
-impl SlackHandler {
-    fn new(command_handler: CommandHandler, buffer_rx: Receiver<String>) -> SlackHandler {
-        SlackHandler {
-            command_handler: command_handler,
-            buffer_rx: buffer_rx
-        }
-    }
+fn new_analytics(published_list: &str, stalled_days: i64) -> Analytics {
+    Analytics::new(EventStore::new(open_database_connection()), published_list, stalled_days)
 }
 
-impl EventHandler for SlackHandler {
-    fn on_event(&mut self, cli: &RtmClient, event: Event) {
-        let sender = cli.sender();
-        if let Event::Message(boxed_message) = event {
-            if let Message::Standard(message) = *boxed_message {
-                self.command_handler.handle_message(message, cli).expect("Slack commands error");
-            }
-            else if let Message::BotMessage(_) = *boxed_message {
-                loop {
-                    match self.buffer_rx.try_recv() {
-                        Ok(channel_message) => {
-                            let split_channel_message: Vec<&str> = channel_message.split("|").collect();
-                            sender.send_message(split_channel_message[0], split_channel_message[1]).expect("Slack sender error");
-                        }
-                        Err(mpsc::TryRecvError::Empty) => break,
-                        Err(mpsc::TryRecvError::Disconnected) => panic!("Board listener detached!")
-                    }
-                }
-            }
-        }
-    }
-
-    fn on_connect(&mut self, _cli: &RtmClient) {
-        info!("v{} connected.", env::var("CARGO_PKG_VERSION").unwrap());
-    }
-
-    fn on_close(&mut self, _cli: &RtmClient) {
-        info!("v{} disconnected.", env::var("CARGO_PKG_VERSION").unwrap());
+// Slack and the webhook sinks need nothing set up beforehand, while email is only available once there is an SMTP server
+fn new_sinks(notifications: NotificationSender, templates: &Templates) -> NotificationSinks {
+    let mut sinks = NotificationSinks::new();
+    sinks.add(Box::new(SlackSink::new(notifications)));
+    if let Ok(smtp_host) = env::var("SMTP_HOST") {
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None
+        };
+        let from = env::var("SMTP_FROM").expect("SMTP sender address not found");
+        sinks.add(Box::new(EmailSink::new(&smtp_host, credentials, &from, templates.clone())));
     }
+    sinks.add(Box::new(WebhookSink::discord(templates.clone())));
+    sinks.add(Box::new(WebhookSink::teams(templates.clone())));
+    sinks.add(Box::new(WebhookSink::webhook(templates.clone())));
+    sinks
 }
 
-fn open_database_connection() -> Database {
-    return Client::connect(MONGODB_HOSTNAME, MONGODB_PORT).expect("MongoDB connection error").db(MONGODB_DATABASE);
+fn new_command_handler(supervisor: SupervisorHandle, trello_client: &Arc<TrelloApi>, board_id: &str, analytics: Analytics, notifications: NotificationSender,
+                       templates: Templates) -> CommandHandler {
+    CommandHandler::new(open_database_connection(), analytics, supervisor,
+        BoardHandler::new(board_id, Arc::clone(trello_client)), CardHandler::new(Arc::clone(trello_client)), new_sinks(notifications, &templates), templates)
 }
 
 fn main() {
@@ -95,50 +133,154 @@ fn main() {
 
     // Get all environment variables
     let slack_api_key = env::var("SLACK_API_KEY").expect("Slack API key not found");
-    let slack_webhook = env::var("SLACK_WEBHOOK").expect("Slack webhook not found");
+    let slack_transport = env::var("SLACK_TRANSPORT").unwrap_or(DEFAULT_SLACK_TRANSPORT.to_string());
     let trello_api_key = env::var("TRELLO_API_KEY").expect("Trello API key not found");
     let trello_oauth_token = env::var("TRELLO_OAUTH_TOKEN").expect("Trello OAuth token not found");
     let trello_board_id = env::var("TRELLO_BOARD_ID").expect("Trello board ID not found");
+    let trello_api_url = env::var("TRELLO_API_URL").unwrap_or(API_URL.to_string());
+    let published_list = env::var("TRELLO_PUBLISHED_LIST").unwrap_or(DEFAULT_PUBLISHED_LIST.to_string());
+    let stalled_days = env::var("STALLED_DAYS").ok().and_then(|days| days.parse::<i64>().ok()).unwrap_or(DEFAULT_STALLED_DAYS);
+    let slack_report_channel = env::var("SLACK_REPORT_CHANNEL").ok();
+    let stale_lists : Vec<String> = env::var("STALE_LISTS").unwrap_or(String::new()).split(',')
+        .map(|list| list.trim().to_string())
+        .filter(|list| !list.is_empty())
+        .collect();
+    let stale_days = env::var("STALE_DAYS").ok().and_then(|days| days.parse::<i64>().ok()).unwrap_or(DEFAULT_STALE_DAYS);
+    let templates_file = env::var("TEMPLATES_FILE").ok();
+    let admin_uids : Vec<String> = env::var("ADMINS").unwrap_or(String::new()).split(',')
+        .map(|uid| uid.trim().to_string())
+        .filter(|uid| !uid.is_empty())
+        .collect();
+
+    // Templates are checked up front, so a typo in an override stops articlebot here instead of garbling messages later
+    let templates = Templates::load(templates_file.as_ref().map(|path| &path[..])).unwrap_or_else(|e| panic!("Invalid templates: {}", e));
+
+    // Admins from the environment are only ever added, so anyone granted the role later keeps it across restarts
+    let admins = Admins::new(open_database_connection());
+    for uid in admin_uids.iter() {
+        if admins.grant(uid).expect("Could not set up admins") {
+            info!("{} is now an admin.", uid);
+        }
+    }
+
+    // Every Trello request goes through one client, so they all share the same rate limit
+    let trello_client : Arc<TrelloApi> = Arc::new(TrelloClient::new(&trello_api_url, &trello_api_key, &trello_oauth_token));
 
-    let (tx, rx) = mpsc::channel();
-    let buffer_count = Arc::new(Mutex::new(0));
+    let (tx, rx) = mpsc::sync_channel(NOTIFICATION_BUFFER_SIZE);
+    let buffer_count = Arc::new(AtomicUsize::new(0));
+    let notifications = NotificationSender::new(tx, Arc::clone(&buffer_count));
+
+    // Background workers are restarted by the supervisor whenever they panic
+    let supervisor = Supervisor::new();
 
     // Slack webhook occasionally sends messages to the #articlebot-reserved to flush message buffer
-    let webhook_buffer_count = Arc::clone(&buffer_count);
-    thread::spawn(move || {
-        let slack = Slack::new(&slack_webhook[..]).unwrap();
-        loop {
-            // This inner scope is necessary because it forces the Mutex to unlock before the thread sleeps
-            {
-                let mut message_count = webhook_buffer_count.lock().unwrap();
-                if *message_count > 0 {
-                    info!("Flushing {} messages in buffer ...", *message_count);
+    // The RTM transport can only send from within its event handler, so it relies on these webhook messages to wake it up
+    if slack_transport == "rtm" {
+        let slack_webhook = env::var("SLACK_WEBHOOK").expect("Slack webhook not found");
+        let webhook_buffer_count = Arc::clone(&buffer_count);
+        supervisor.spawn("webhook-flusher", move || {
+            let slack = Slack::new(&slack_webhook[..]).unwrap();
+            loop {
+                // Only the messages counted here are taken off, so any sent in the meantime wait for the next flush
+                let message_count = webhook_buffer_count.load(Ordering::SeqCst);
+                if message_count > 0 {
+                    info!("Flushing {} messages in buffer ...", message_count);
 
                     let payload = PayloadBuilder::new()
-                      .text(&format!("articlebot is now flushing {} messages in its internal mpsc channel.", *message_count)[..])
+                      .text(&format!("articlebot is now flushing {} messages in its internal mpsc channel.", message_count)[..])
                       .build()
                       .unwrap();
 
                     slack.send(&payload).expect("Webhook flush error");
 
-                    *message_count = 0;
+                    webhook_buffer_count.fetch_sub(message_count, Ordering::SeqCst);
                 }
+                thread::sleep(Duration::from_secs(TRY_FLUSH_INTERVAL));
             }
-            thread::sleep(Duration::from_secs(TRY_FLUSH_INTERVAL));
+        });
+    }
+
+    // Offload the Trello updater to its own thread so it doesn't block the main thread
+    let poller_notifications = notifications.clone();
+    let poller_trello_client = Arc::clone(&trello_client);
+    let poller_board_id = trello_board_id.clone();
+    let poller_templates = templates.clone();
+    // Made outside the poller, so that when it is restarted it picks up from its last poll and retries what it failed on
+    let poll_state = PollState::new();
+    supervisor.spawn("trello-poller", move || {
+        // The listeners share one card handler, so a card fetched for one of them is cached for the rest
+        let card_handler = Arc::new(CardHandler::new(Arc::clone(&poller_trello_client)));
+        let mut board_handler = BoardHandler::with_state(&poller_board_id, Arc::clone(&poller_trello_client), Arc::clone(&poll_state));
+        let events = EventStore::new(open_database_connection());
+        if let Err(e) = events.index_latest_moves() {
+            error!("Failed to index the latest move of each card: {}", e);
         }
+        board_handler.add_listener(Box::new(HistoryListener::new(events, Arc::clone(&card_handler))));
+        board_handler.add_listener(Box::new(RelayActionListener::new(open_database_connection(), card_handler, new_sinks(poller_notifications.clone(), &poller_templates),
+            poller_templates.clone())));
+        board_handler.listen();
     });
 
-    // Offload the Trello updater to its own thread so it doesn't block the main thread
-    let trello_buffer_count = Arc::clone(&buffer_count);
-    thread::spawn(move || {
-        let card_handler = CardHandler::new(&trello_api_key, &trello_oauth_token);
-        let action_listener = RelayActionListener::new(open_database_connection(), card_handler, tx, trello_buffer_count);
-        let mut board_handler = BoardHandler::new(&trello_board_id, &trello_api_key, &trello_oauth_token, action_listener);
-        board_handler.listen().expect("Trello handler error");
+    // Periodic jobs, such as reminders and the weekly pipeline report
+    let scheduler_published_list = published_list.clone();
+    let scheduler_notifications = notifications.clone();
+    let scheduler_trello_client = Arc::clone(&trello_client);
+    let scheduler_board_id = trello_board_id.clone();
+    let scheduler_api_key = slack_api_key.clone();
+    let scheduler_templates = templates.clone();
+    supervisor.spawn("scheduler", move || {
+        let mut scheduler = Scheduler::new(open_database_connection());
+        let new_reminder = || Reminder::new(open_database_connection(), CardHandler::new(Arc::clone(&scheduler_trello_client)),
+            WebApiOutput::new(&scheduler_api_key), scheduler_notifications.clone(), scheduler_templates.clone());
+        let new_board_handler = || BoardHandler::new(&scheduler_board_id, Arc::clone(&scheduler_trello_client));
+
+        scheduler.add_job(Box::new(StaleCardJob::new(EventStore::new(open_database_connection()), new_board_handler(), new_reminder(), &stale_lists, stale_days)));
+        scheduler.add_job(Box::new(DueDateJob::new(open_database_connection(), new_board_handler(), new_reminder())));
+        if let Some(ref channel) = slack_report_channel {
+            let analytics = new_analytics(&scheduler_published_list, stalled_days);
+            scheduler.add_job(Box::new(WeeklyReportJob::new(analytics, channel, scheduler_notifications.clone(), scheduler_templates.clone())));
+        }
+        scheduler.run();
     });
 
-    // Slack event handler
-    let command_handler = CommandHandler::new(open_database_connection());
-    let mut slack_handler = SlackHandler::new(command_handler, rx);
-    RtmClient::login_and_run(&slack_api_key, &mut slack_handler).expect("Slack client error");
+    // Slash commands and notification buttons are delivered over HTTP, which the RTM transport needs a server of its own for
+    let slack_signing_secret = env::var("SLACK_SIGNING_SECRET").ok();
+    let slack_http_address = env::var("SLACK_HTTP_ADDRESS").unwrap_or(DEFAULT_HTTP_ADDRESS.to_string());
+    if let (true, Some(signing_secret)) = (slack_transport == "rtm", slack_signing_secret.clone()) {
+        let http_api_key = slack_api_key.clone();
+        let http_address = slack_http_address.clone();
+        let http_supervisor = supervisor.handle();
+        let http_trello_client = Arc::clone(&trello_client);
+        let http_board_id = trello_board_id.clone();
+        let http_published_list = published_list.clone();
+        let http_notifications = notifications.clone();
+        let http_templates = templates.clone();
+        supervisor.spawn("slack-http", move || {
+            let analytics = new_analytics(&http_published_list, stalled_days);
+            let command_handler = new_command_handler(http_supervisor.clone(), &http_trello_client, &http_board_id, analytics, http_notifications.clone(),
+                http_templates.clone());
+            let server = SlackHttpServer::new(&http_address, &signing_secret, &http_api_key, false);
+            loop {
+                server.serve(&command_handler, Duration::from_secs(TRY_FLUSH_INTERVAL));
+            }
+        });
+    }
+    else if slack_transport == "rtm" {
+        warn!("No Slack signing secret found, so slash commands and notification buttons are disabled.");
+    }
+
+    // Slack event handler, over whichever transport has been configured. It runs on the thread that flushes the buffer,
+    // so it can't wait for room in it.
+    let command_handler = new_command_handler(supervisor.handle(), &trello_client, &trello_board_id, new_analytics(&published_list, stalled_days),
+        notifications.non_blocking(), templates.clone());
+    let mut buffer = NotificationBuffer::new(rx, templates.clone());
+    let mut transport : Box<SlackTransport> = match &slack_transport[..] {
+        "events" => {
+            let signing_secret = slack_signing_secret.expect("Slack signing secret not found");
+            Box::new(EventsApiTransport::new(&slack_http_address, &signing_secret, &slack_api_key))
+        }
+        "rtm" => Box::new(RtmTransport::new(&slack_api_key)),
+        other => panic!("Unknown Slack transport \"{}\"", other)
+    };
+    transport.run(&command_handler, &mut buffer);
 }
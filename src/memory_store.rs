@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+use store::Store;
+
+use bson::{Bson, Document};
+
+// Understands just enough of MongoDB's query and update operators to stand in for it in tests
+pub struct MemoryStore {
+    collections: Mutex<HashMap<String, Vec<Document>>>
+}
+
+fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    match (a, b) {
+        (&Bson::String(ref a), &Bson::String(ref b)) => Some(a.cmp(b)),
+        (&Bson::UtcDatetime(ref a), &Bson::UtcDatetime(ref b)) => Some(a.cmp(b)),
+        (&Bson::Boolean(a), &Bson::Boolean(b)) => Some(a.cmp(&b)),
+        _ => match (as_number(a), as_number(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => None
+        }
+    }
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+    match *value {
+        Bson::I32(value) => Some(value as f64),
+        Bson::I64(value) => Some(value as f64),
+        Bson::FloatingPoint(value) => Some(value),
+        _ => None
+    }
+}
+
+fn equals(a: &Bson, b: &Bson) -> bool {
+    compare(a, b) == Some(Ordering::Equal) || a == b
+}
+
+fn is_operator_document(value: &Bson) -> bool {
+    match *value {
+        Bson::Document(ref document) => document.keys().next().map_or(false, |key| key.starts_with("$")),
+        _ => false
+    }
+}
+
+fn matches_condition(value: Option<&Bson>, condition: &Bson) -> bool {
+    if let Bson::Document(ref operators) = *condition {
+        if is_operator_document(condition) {
+            return operators.iter().all(|(operator, operand)| {
+                let ordering = value.and_then(|value| compare(value, operand));
+                match &operator[..] {
+                    "$gt" => ordering == Some(Ordering::Greater),
+                    "$gte" => ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal),
+                    "$lt" => ordering == Some(Ordering::Less),
+                    "$lte" => ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal),
+                    "$ne" => !matches_condition(value, operand),
+                    "$in" => match *operand {
+                        Bson::Array(ref candidates) => candidates.iter().any(|candidate| matches_condition(value, candidate)),
+                        _ => false
+                    },
+                    other => panic!("MemoryStore does not support the {} operator", other)
+                }
+            });
+        }
+    }
+
+    match value {
+        // Like MongoDB, a scalar condition matches an array that contains it
+        Some(&Bson::Array(ref values)) if !is_array(condition) => values.iter().any(|value| equals(value, condition)),
+        Some(value) => equals(value, condition),
+        None => *condition == Bson::Null
+    }
+}
+
+fn is_array(value: &Bson) -> bool {
+    match *value {
+        Bson::Array(_) => true,
+        _ => false
+    }
+}
+
+fn matches(document: &Document, filter: &Document) -> bool {
+    filter.iter().all(|(key, condition)| {
+        if key == "$or" {
+            return match *condition {
+                Bson::Array(ref filters) => filters.iter().any(|filter| match *filter {
+                    Bson::Document(ref filter) => matches(document, filter),
+                    _ => false
+                }),
+                _ => false
+            };
+        }
+        matches_condition(document.get(key), condition)
+    })
+}
+
+fn apply_update(document: &mut Document, update: &Document) {
+    let is_operator_update = update.keys().next().map_or(false, |key| key.starts_with("$"));
+    if !is_operator_update {
+        // A plain document replaces everything but the ID
+        let id = document.get("_id").cloned();
+        *document = update.clone();
+        if let Some(id) = id {
+            document.insert("_id", id);
+        }
+        return;
+    }
+
+    for (operator, fields) in update.iter() {
+        let fields = match *fields {
+            Bson::Document(ref fields) => fields,
+            _ => panic!("{} expects a document", operator)
+        };
+        for (key, value) in fields.iter() {
+            match &operator[..] {
+                "$set" => {
+                    document.insert(key.clone(), value.clone());
+                }
+                "$unset" => {
+                    document.remove(key);
+                }
+                "$push" | "$addToSet" => {
+                    let mut values = document.get_array(key).map(|values| values.clone()).unwrap_or(Vec::new());
+                    if operator == "$push" || !values.iter().any(|existing| equals(existing, value)) {
+                        values.push(value.clone());
+                    }
+                    document.insert(key.clone(), Bson::Array(values));
+                }
+                "$pull" => {
+                    if let Ok(values) = document.get_array(key).map(|values| values.clone()) {
+                        let remaining : Vec<Bson> = values.into_iter().filter(|existing| !matches_condition(Some(existing), value)).collect();
+                        document.insert(key.clone(), Bson::Array(remaining));
+                    }
+                }
+                other => panic!("MemoryStore does not support the {} operator", other)
+            }
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            collections: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn all(&self, collection: &str) -> Vec<Document> {
+        self.collections.lock().unwrap().get(collection).cloned().unwrap_or(Vec::new())
+    }
+}
+
+impl Store for MemoryStore {
+    fn find(&self, collection: &str, filter: Document, sort: Option<Document>) -> Result<Vec<Document>, Box<Error>> {
+        let mut documents : Vec<Document> = self.all(collection).into_iter().filter(|document| matches(document, &filter)).collect();
+
+        if let Some(sort) = sort {
+            documents.sort_by(|a, b| {
+                for (key, direction) in sort.iter() {
+                    let ordering = match (a.get(key), b.get(key)) {
+                        (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+                        (Some(_), None) => Ordering::Greater,
+                        (None, Some(_)) => Ordering::Less,
+                        (None, None) => Ordering::Equal
+                    };
+                    let ordering = if as_number(direction).unwrap_or(1.0) < 0.0 { ordering.reverse() } else { ordering };
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                }
+                Ordering::Equal
+            });
+        }
+
+        Ok(documents)
+    }
+
+    fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>, Box<Error>> {
+        Ok(self.find(collection, filter, None)?.into_iter().next())
+    }
+
+    fn find_one_and_delete(&self, collection: &str, filter: Document) -> Result<Option<Document>, Box<Error>> {
+        let mut collections = self.collections.lock().unwrap();
+        let documents = collections.entry(collection.to_string()).or_insert(Vec::new());
+        match documents.iter().position(|document| matches(document, &filter)) {
+            Some(index) => Ok(Some(documents.remove(index))),
+            None => Ok(None)
+        }
+    }
+
+    fn insert_one(&self, collection: &str, document: Document) -> Result<(), Box<Error>> {
+        self.collections.lock().unwrap().entry(collection.to_string()).or_insert(Vec::new()).push(document);
+        Ok(())
+    }
+
+    fn update_one(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> Result<i32, Box<Error>> {
+        let mut collections = self.collections.lock().unwrap();
+        let documents = collections.entry(collection.to_string()).or_insert(Vec::new());
+
+        if let Some(document) = documents.iter_mut().find(|document| matches(document, &filter)) {
+            apply_update(document, &update);
+            return Ok(1);
+        }
+
+        if upsert {
+            // Upserted documents start out with whatever the filter asked for by equality
+            let mut document = Document::new();
+            for (key, condition) in filter.iter() {
+                if !key.starts_with("$") && !is_operator_document(condition) {
+                    document.insert(key.clone(), condition.clone());
+                }
+            }
+            apply_update(&mut document, &update);
+            documents.push(document);
+        }
+        Ok(0)
+    }
+
+    fn delete_one(&self, collection: &str, filter: Document) -> Result<i32, Box<Error>> {
+        Ok(self.find_one_and_delete(collection, filter)?.map_or(0, |_| 1))
+    }
+
+    fn delete_many(&self, collection: &str, filter: Document) -> Result<i32, Box<Error>> {
+        let mut collections = self.collections.lock().unwrap();
+        let documents = collections.entry(collection.to_string()).or_insert(Vec::new());
+        let before = documents.len();
+        documents.retain(|document| !matches(document, &filter));
+        Ok((before - documents.len()) as i32)
+    }
+}
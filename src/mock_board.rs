@@ -0,0 +1,276 @@
+use chrono::prelude::*;
+use serde_json;
+use serde_json::{Map, Value};
+use url::form_urlencoded;
+
+const CARD_URL: &'static str = "https://trello.com/c";
+// Changes made through the API are credited to whoever owns the token, which for articlebot is articlebot itself
+const API_MEMBER_ID: &'static str = "5a1000000000000000000000";
+pub const API_USERNAME: &'static str = "articlebot";
+
+// A Trello board kept in memory that answers the requests articlebot makes, so it can be run and tested without
+// touching a real board. Cards can be created, moved and commented on, and each change is recorded as an action.
+pub struct MockBoard {
+    pub id: String,
+    lists: Vec<Value>,
+    members: Vec<Value>,
+    cards: Vec<Value>,
+    actions: Vec<Value>,
+    next_id: u32
+}
+
+fn split_route(route: &str) -> (&str, &str) {
+    let mut parts = route.splitn(2, '?');
+    (parts.next().unwrap(), parts.next().unwrap_or(""))
+}
+
+pub fn query_param(query: &str, name: &str) -> Option<String> {
+    form_urlencoded::parse(query.as_bytes()).find(|&(ref key, _)| key == name).map(|(_, value)| value.into_owned())
+}
+
+// Like Trello, only ISO 8601 dates are understood
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date).ok().map(|date| date.with_timezone(&Utc))
+}
+
+fn matches_filter(action: &Value, filter: &Option<String>) -> bool {
+    match *filter {
+        // Filters like updateCard:idList narrow a type down further, but every updateCard here is a move anyway
+        Some(ref filter) => filter.split(',').any(|action_type| action["type"] == action_type.split(':').next().unwrap()),
+        None => true
+    }
+}
+
+fn to_json(value: &Value) -> Result<String, u16> {
+    Ok(value.to_string())
+}
+
+impl MockBoard {
+    pub fn new(id: &str, lists: Vec<Value>, members: Vec<Value>) -> MockBoard {
+        MockBoard {
+            id: id.to_string(),
+            lists: lists,
+            members: members,
+            cards: Vec::new(),
+            actions: Vec::new(),
+            next_id: 0
+        }
+    }
+
+    // Reads a board from the same kind of JSON Trello returns, i.e. an ID along with lists, members and cards
+    pub fn from_json(board: &Value) -> Result<MockBoard, String> {
+        let id = board["id"].as_str().ok_or("The board needs an id")?;
+        let array = |key: &str| board[key].as_array().cloned().unwrap_or(Vec::new());
+
+        let mut mock_board = MockBoard::new(id, array("lists"), array("members"));
+        for card in array("cards") {
+            let name = card["name"].as_str().ok_or("Every card needs a name")?;
+            let short_link = card["shortLink"].as_str().ok_or("Every card needs a shortLink")?;
+            let list = card["list"].as_str().ok_or("Every card needs a list")?;
+            let members : Vec<&str> = card["members"].as_array().map(|members| members.iter().filter_map(|member| member.as_str()).collect()).unwrap_or(Vec::new());
+            mock_board.add_card(short_link, name, list, &members)?;
+        }
+        Ok(mock_board)
+    }
+
+    fn new_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{:020}", prefix, self.next_id)
+    }
+
+    // Lists and members can be referred to by ID or by name, like they are in articlebot's commands
+    fn list(&self, list_ref: &str) -> Option<&Value> {
+        self.lists.iter().find(|list| list["id"] == list_ref || list["name"].as_str().map(|name| name.to_lowercase()) == Some(list_ref.to_lowercase()))
+    }
+
+    fn member(&self, member_ref: &str) -> Option<Value> {
+        if member_ref == API_USERNAME || member_ref == API_MEMBER_ID {
+            return Some(json!({ "id": API_MEMBER_ID, "fullName": "articlebot", "initials": "A", "username": API_USERNAME }));
+        }
+        self.members.iter().find(|member| member["id"] == member_ref || member["username"] == member_ref).cloned()
+    }
+
+    fn card_index(&self, card_ref: &str) -> Option<usize> {
+        self.cards.iter().position(|card| card["id"] == card_ref || card["shortLink"] == card_ref)
+    }
+
+    // Cards are served the way articlebot asks for them, with their members nested inside
+    fn card(&self, card_ref: &str) -> Option<Value> {
+        self.card_index(card_ref).map(|index| {
+            let mut card = self.cards[index].clone();
+            let members : Vec<Value> = card["idMembers"].as_array().unwrap().iter()
+                .filter_map(|id| id.as_str().and_then(|id| self.member(id)))
+                .collect();
+            card["members"] = Value::Array(members);
+            card
+        })
+    }
+
+    // Puts a new card on the board and returns its ID
+    pub fn add_card(&mut self, short_link: &str, name: &str, list_ref: &str, members: &[&str]) -> Result<String, String> {
+        let list_id = self.list(list_ref).ok_or(format!("There is no list {}", list_ref))?["id"].clone();
+        let mut member_ids = Vec::new();
+        for member_ref in members {
+            member_ids.push(self.member(member_ref).ok_or(format!("{} is not a member of the board", member_ref))?["id"].clone());
+        }
+
+        let id = self.new_id("5a30");
+        self.cards.push(json!({
+            "id": &id,
+            "name": name,
+            "desc": "",
+            "idBoard": &self.id,
+            "idList": list_id,
+            "idMembers": member_ids,
+            "shortLink": short_link,
+            "url": format!("{}/{}", CARD_URL, short_link),
+            "closed": false,
+            "actions": []
+        }));
+        Ok(id)
+    }
+
+    fn push_action(&mut self, action_type: &str, member_ref: &str, card_ref: &str, mut data: Value) -> Result<(), String> {
+        let creator = self.member(member_ref).ok_or(format!("{} is not a member of the board", member_ref))?;
+        data["board"] = json!({ "id": &self.id });
+        data["card"] = {
+            let card = &self.cards[self.card_index(card_ref).ok_or(format!("There is no card {}", card_ref))?];
+            json!({ "id": &card["id"], "name": &card["name"], "shortLink": &card["shortLink"], "idList": &card["idList"] })
+        };
+
+        let id = self.new_id("5b10");
+        self.actions.push(json!({
+            "id": id,
+            "idMemberCreator": &creator["id"],
+            "data": data,
+            "type": action_type,
+            "date": Utc::now().to_rfc3339(),
+            "memberCreator": creator
+        }));
+        Ok(())
+    }
+
+    pub fn move_card(&mut self, member_ref: &str, card_ref: &str, list_ref: &str) -> Result<(), String> {
+        let index = self.card_index(card_ref).ok_or(format!("There is no card {}", card_ref))?;
+        let list_before = self.list(self.cards[index]["idList"].as_str().unwrap()).cloned().unwrap();
+        let list_after = self.list(list_ref).cloned().ok_or(format!("There is no list {}", list_ref))?;
+        self.cards[index]["idList"] = list_after["id"].clone();
+
+        self.push_action("updateCard", member_ref, card_ref, json!({
+            "listBefore": { "id": &list_before["id"], "name": &list_before["name"] },
+            "listAfter": { "id": &list_after["id"], "name": &list_after["name"] },
+            "old": { "idList": &list_before["id"] }
+        }))
+    }
+
+    pub fn comment(&mut self, member_ref: &str, card_ref: &str, text: &str) -> Result<(), String> {
+        self.push_action("commentCard", member_ref, card_ref, json!({
+            "text": text
+        }))
+    }
+
+    // Actions come back newest first, like they do from Trello, which turns down a since it can't read
+    fn find_actions<F : Fn(&Value) -> bool>(&self, query: &str, predicate: F) -> Result<Value, u16> {
+        let filter = query_param(query, "filter");
+        let since = match query_param(query, "since") {
+            Some(since) => Some(parse_date(&since).ok_or(400u16)?),
+            None => None
+        };
+        let actions : Vec<Value> = self.actions.iter().rev()
+            .filter(|action| matches_filter(action, &filter))
+            .filter(|action| since.map_or(true, |since| action["date"].as_str().and_then(parse_date).map_or(false, |date| date > since)))
+            .filter(|action| predicate(action))
+            .cloned()
+            .collect();
+        Ok(Value::Array(actions))
+    }
+
+    fn batch(&self, query: &str) -> Value {
+        // Like Trello, the URLs are only split on commas once they have been decoded, commas inside them included
+        let urls = query_param(query, "urls").unwrap_or(String::new());
+        let results : Vec<Value> = urls.split(',').map(|route| {
+            let (path, query) = split_route(route);
+            let mut result = Map::new();
+            match self.get(path, query) {
+                Ok(body) => result.insert("200".to_string(), serde_json::from_str(&body).unwrap()),
+                Err(status) => result.insert(status.to_string(), json!("The requested resource was not found."))
+            };
+            Value::Object(result)
+        }).collect();
+        Value::Array(results)
+    }
+
+    // Answers a request to the Trello API, given the route relative to the API's base URL
+    pub fn handle(&mut self, method: &str, route: &str) -> Result<String, u16> {
+        let (path, query) = split_route(route);
+        let segments : Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (method, &segments[..]) {
+            ("GET", _) => self.get(path, query),
+            ("PUT", &["cards", card_ref]) => {
+                let list_ref = query_param(query, "idList").ok_or(400u16)?;
+                self.move_card(API_USERNAME, card_ref, &list_ref).map_err(|_| 400u16)?;
+                to_json(&self.card(card_ref).unwrap())
+            }
+            ("POST", &["cards", card_ref, "actions", "comments"]) => {
+                let text = query_param(query, "text").ok_or(400u16)?;
+                self.comment(API_USERNAME, card_ref, &text).map_err(|_| 404u16)?;
+                to_json(self.actions.last().unwrap())
+            }
+            ("POST", &["cards", card_ref, "idMembers"]) => {
+                let member = self.member(&query_param(query, "value").ok_or(400u16)?).ok_or(400u16)?;
+                let index = self.card_index(card_ref).ok_or(404u16)?;
+                self.cards[index]["idMembers"].as_array_mut().unwrap().push(member["id"].clone());
+                to_json(&self.cards[index]["idMembers"])
+            }
+            _ => Err(404)
+        }
+    }
+
+    fn get(&self, path: &str, query: &str) -> Result<String, u16> {
+        let segments : Vec<&str> = path.trim_matches('/').split('/').collect();
+        if segments[0] == "boards" && segments.get(1) != Some(&&self.id[..]) {
+            return Err(404);
+        }
+
+        match &segments[..] {
+            &["boards", _, "actions"] => to_json(&self.find_actions(query, |_| true)?),
+            &["boards", _, "lists"] => to_json(&Value::Array(self.lists.clone())),
+            &["boards", _, "cards"] => to_json(&Value::Array(self.cards.iter().filter_map(|card| self.card(card["id"].as_str().unwrap())).collect())),
+            &["boards", _, "memberships"] => {
+                let memberships : Vec<Value> = self.members.iter().map(|member| json!({
+                    "id": &member["id"],
+                    "idMember": &member["id"],
+                    "memberType": member["memberType"].as_str().unwrap_or("normal"),
+                    "member": member
+                })).collect();
+                to_json(&Value::Array(memberships))
+            }
+            &["cards", card_ref] => to_json(&self.card(card_ref).ok_or(404u16)?),
+            &["cards", card_ref, "actions"] => {
+                let card_id = self.card(card_ref).ok_or(404u16)?["id"].clone();
+                to_json(&self.find_actions(query, |action| action["data"]["card"]["id"] == card_id)?)
+            }
+            &["members", member_ref] => to_json(&self.member(member_ref).ok_or(404u16)?),
+            &["lists", list_ref] => to_json(self.list(list_ref).ok_or(404u16)?),
+            &["lists", list_ref, "cards"] => {
+                let list_id = self.list(list_ref).ok_or(404u16)?["id"].clone();
+                let cards : Vec<Value> = self.cards.iter().filter(|card| card["idList"] == list_id).cloned().collect();
+                to_json(&Value::Array(cards))
+            }
+            &["batch"] => to_json(&self.batch(query)),
+            _ => Err(404)
+        }
+    }
+
+    // The whole board, for checking on it from outside
+    pub fn state(&self) -> Value {
+        json!({
+            "id": &self.id,
+            "lists": &self.lists,
+            "members": &self.members,
+            "cards": &self.cards,
+            "actions": &self.actions
+        })
+    }
+}
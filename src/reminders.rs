@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use history::EventStore;
+use links::Links;
+use store::Store;
+use scheduler::Job;
+use slack_transport::{Notification, NotificationCard, NotificationSender, SlackOutput};
+use slack_web::WebApiOutput;
+use templates::{Templates, LanguagePreferences};
+use trello::{BoardHandler, CardHandler};
+use trello_models::Card;
+
+use bson::{Bson, Document};
+use chrono::prelude::*;
+use chrono::Duration;
+
+const SNOOZES_COLLECTION: &'static str = "snoozes";
+const OPT_OUTS_COLLECTION: &'static str = "reminder_opt_outs";
+const SENT_REMINDERS_COLLECTION: &'static str = "reminders";
+const STALE_INTERVAL_HOURS: i64 = 24;
+const DUE_INTERVAL_MINUTES: i64 = 15;
+const OVERDUE_WINDOW_HOURS: i64 = 24;
+
+pub struct ReminderPreferences {
+    store: Arc<Store>
+}
+
+impl ReminderPreferences {
+    pub fn new(store: Arc<Store>) -> ReminderPreferences {
+        ReminderPreferences {
+            store: store
+        }
+    }
+
+    pub fn snooze(&self, uid: &str, card_id: &str, until: DateTime<Utc>) -> Result<(), Box<Error>> {
+        self.store.update_one(SNOOZES_COLLECTION, doc! {
+            "uid": uid,
+            "card": card_id
+        }, doc! {
+            "$set": { "until": Bson::UtcDatetime(until) }
+        }, true)?;
+
+        Ok(())
+    }
+
+    pub fn is_snoozed(&self, uid: &str, card_id: &str) -> Result<bool, Box<Error>> {
+        let snoozed = self.store.find_one(SNOOZES_COLLECTION, doc! {
+            "uid": uid,
+            "card": card_id,
+            "until": { "$gt": Bson::UtcDatetime(Utc::now()) }
+        })?;
+        Ok(snoozed.is_some())
+    }
+
+    pub fn set_enabled(&self, uid: &str, enabled: bool) -> Result<(), Box<Error>> {
+        self.store.delete_many(OPT_OUTS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        if !enabled {
+            self.store.insert_one(OPT_OUTS_COLLECTION, doc! {
+                "uid": uid
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, uid: &str) -> Result<bool, Box<Error>> {
+        let opt_out = self.store.find_one(OPT_OUTS_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        Ok(opt_out.is_none())
+    }
+}
+
+// Works out who should hear about a card, and sends them a reminder they can snooze
+pub struct Reminder {
+    store: Arc<Store>,
+    preferences: ReminderPreferences,
+    languages: LanguagePreferences,
+    links: Links,
+    card_handler: CardHandler,
+    slack: WebApiOutput,
+    notifications: NotificationSender,
+    templates: Templates
+}
+
+impl Reminder {
+    pub fn new(store: Arc<Store>, card_handler: CardHandler, slack: WebApiOutput, notifications: NotificationSender, templates: Templates) -> Reminder {
+        Reminder {
+            preferences: ReminderPreferences::new(Arc::clone(&store)),
+            languages: LanguagePreferences::new(Arc::clone(&store)),
+            links: Links::new(Arc::clone(&store)),
+            store: store,
+            card_handler: card_handler,
+            slack: slack,
+            notifications: notifications,
+            templates: templates
+        }
+    }
+
+    pub fn prefetch(&self, cards: &[&Card]) -> Result<(), Box<Error>> {
+        let card_ids : Vec<&str> = cards.iter().map(|card| &card.id[..]).collect();
+        self.card_handler.prefetch_cards(&card_ids)
+    }
+
+    // Card members are reached through their linked Slack accounts, along with anyone tracking them
+    fn recipients(&self, card: &Card) -> Result<Vec<(String, String)>, Box<Error>> {
+        let mut uids : Vec<String> = Vec::new();
+        for member in self.card_handler.get_card_members(card)? {
+            uids.extend(self.links.slack_users(&member.username)?);
+
+            if let Some(tdoc) = self.store.find_one("trello", doc! {
+                "name": &member.username
+            })? {
+                for tracker in tdoc.get_array("trackers").unwrap() {
+                    uids.push(tracker.as_str().unwrap().to_string());
+                }
+            }
+        }
+        uids.sort();
+        uids.dedup();
+
+        let mut recipients = Vec::new();
+        for uid in uids {
+            if !self.preferences.is_enabled(&uid)? {
+                info!("{} has turned reminders off.", uid);
+                continue;
+            }
+            if self.preferences.is_snoozed(&uid, &card.id)? {
+                info!("{} has snoozed reminders for this card.", uid);
+                continue;
+            }
+
+            // Trackers already have a channel on record, anyone else gets a DM opened for them
+            let channel = match self.store.find_one("slack", doc! {
+                "uid": &uid
+            })? {
+                Some(sdoc) => {
+                    if let Ok(muted) = sdoc.get_array("muted") {
+                        if muted.iter().any(|m| *m == Bson::String(card.id.clone()) || *m == Bson::String(card.short_link.clone())) {
+                            info!("{} has muted this card.", uid);
+                            continue;
+                        }
+                    }
+                    sdoc.get_str("cid").unwrap().to_string()
+                }
+                None => match self.slack.open_dm(&uid) {
+                    Ok(channel) => channel,
+                    Err(e) => {
+                        warn!("Could not open a DM with {}: {}", uid, e);
+                        continue;
+                    }
+                }
+            };
+            recipients.push((uid, channel));
+        }
+        Ok(recipients)
+    }
+
+    // Reminders are rendered separately for each recipient, in their own language. One recipient failing doesn't
+    // keep the rest from being reminded, so the reminder still counts as sent and nobody gets it twice.
+    // Dates are given as (variable, date template, date), since how they are written depends on the language too.
+    pub fn send(&self, card: &Card, template: &str, variables: &[(&str, &str)], dates: &[(&str, &str, DateTime<Utc>)]) -> Result<(), Box<Error>> {
+        for (uid, channel) in self.recipients(card)? {
+            info!("Reminding {} about card \"{}\".", uid, card.name);
+            if let Err(e) = self.send_to(&uid, &channel, card, template, variables, dates) {
+                error!("Could not remind {} about card {}: {}", uid, card.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn send_to(&self, uid: &str, channel: &str, card: &Card, template: &str, variables: &[(&str, &str)], dates: &[(&str, &str, DateTime<Utc>)]) -> Result<(), Box<Error>> {
+        let language = self.languages.get(uid)?;
+        let templates = self.templates.in_language(&language);
+        let formatted : Vec<(&str, String)> = dates.iter()
+            .map(|&(name, template, date)| (name, templates.format_date(template, &date)))
+            .collect();
+        let mut variables = variables.to_vec();
+        variables.extend(formatted.iter().map(|&(name, ref date)| (name, &date[..])));
+        self.notifications.send(Notification {
+            channel: channel.to_string(),
+            text: templates.render(template, &variables),
+            language: language,
+            card: Some(NotificationCard {
+                id: card.id.clone(),
+                url: card.url.clone(),
+                writer: String::new(),
+                snooze: true
+            })
+        })?;
+        Ok(())
+    }
+}
+
+pub struct StaleCardJob {
+    events: EventStore,
+    board_handler: BoardHandler,
+    reminder: Reminder,
+    stale_lists: Vec<String>,
+    stale_days: i64
+}
+
+impl StaleCardJob {
+    pub fn new(events: EventStore, board_handler: BoardHandler, reminder: Reminder, stale_lists: &[String], stale_days: i64) -> StaleCardJob {
+        StaleCardJob {
+            events: events,
+            board_handler: board_handler,
+            reminder: reminder,
+            stale_lists: stale_lists.iter().map(|list| list.to_lowercase()).collect(),
+            stale_days: stale_days
+        }
+    }
+
+    fn last_moved(&self, card: &Card) -> Result<Option<DateTime<Utc>>, Box<Error>> {
+        // Moves we have recorded are more precise than Trello's last activity, which also counts comments and edits
+        let events = self.events.card_history(&card.id)?;
+        match events.iter().rev().find(|event| event.is_move()) {
+            Some(event) => Ok(Some(event.date)),
+            None => Ok(card.date_last_activity)
+        }
+    }
+}
+
+impl Job for StaleCardJob {
+    fn name(&self) -> &str {
+        "stale-cards"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::hours(STALE_INTERVAL_HOURS)
+    }
+
+    fn run(&self) -> Result<(), Box<Error>> {
+        if self.stale_lists.is_empty() {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        let list_names : HashMap<String, String> = self.board_handler.get_lists()?.into_iter()
+            .filter(|list| self.stale_lists.contains(&list.name.to_lowercase()))
+            .map(|list| (list.id, list.name))
+            .collect();
+
+        let mut stale = Vec::new();
+        for card in self.board_handler.get_cards()? {
+            if let Some(list_name) = list_names.get(&card.id_list) {
+                if let Some(last_moved) = self.last_moved(&card)? {
+                    let days = now.signed_duration_since(last_moved).num_days();
+                    if days >= self.stale_days {
+                        stale.push((card, list_name, days));
+                    }
+                }
+            }
+        }
+
+        info!("Found {} stale cards.", stale.len());
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let board = self.board_handler.get_board()?;
+        self.reminder.prefetch(&stale.iter().map(|&(ref card, _, _)| card).collect::<Vec<&Card>>())?;
+        for &(ref card, list_name, days) in stale.iter() {
+            let variables = [
+                ("card", &card.name[..]),
+                ("list", list_name),
+                ("days", &days.to_string()),
+                ("url", &card.url),
+                ("board", &board.name)
+            ];
+            // One card's members failing to resolve shouldn't hold up everyone else's reminders
+            if let Err(e) = self.reminder.send(card, "notify.stale", &variables, &[]) {
+                error!("Could not send a reminder about card {}: {}", card.id, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The reminders sent for a due date, from furthest to closest, each of which is only sent once
+#[derive(Clone, Copy, PartialEq)]
+enum DueStage {
+    DayBefore,
+    HourBefore,
+    Overdue
+}
+
+impl DueStage {
+    fn for_due_date(due: DateTime<Utc>, now: DateTime<Utc>) -> Option<DueStage> {
+        let remaining = due.signed_duration_since(now);
+        // Cards that have been overdue for longer were already overdue before articlebot could have noticed,
+        // e.g. on its first run, and reminding everyone about all of them at once helps nobody
+        if remaining <= -Duration::hours(OVERDUE_WINDOW_HOURS) {
+            None
+        }
+        else if remaining <= Duration::zero() {
+            Some(DueStage::Overdue)
+        }
+        else if remaining <= Duration::hours(1) {
+            Some(DueStage::HourBefore)
+        }
+        else if remaining <= Duration::hours(24) {
+            Some(DueStage::DayBefore)
+        }
+        else {
+            None
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match *self {
+            DueStage::DayBefore => "day_before",
+            DueStage::HourBefore => "hour_before",
+            DueStage::Overdue => "overdue"
+        }
+    }
+
+    fn template(&self) -> &'static str {
+        match *self {
+            DueStage::DayBefore => "notify.due_day",
+            DueStage::HourBefore => "notify.due_hour",
+            DueStage::Overdue => "notify.overdue"
+        }
+    }
+
+    fn due_template(&self) -> &'static str {
+        match *self {
+            DueStage::HourBefore => "date.time",
+            _ => "date.day_time"
+        }
+    }
+}
+
+pub struct DueDateJob {
+    store: Arc<Store>,
+    board_handler: BoardHandler,
+    reminder: Reminder
+}
+
+impl DueDateJob {
+    pub fn new(store: Arc<Store>, board_handler: BoardHandler, reminder: Reminder) -> DueDateJob {
+        DueDateJob {
+            store: store,
+            board_handler: board_handler,
+            reminder: reminder
+        }
+    }
+
+    // Sent reminders are keyed by the due date too, so moving a card's deadline starts its reminders over
+    fn sent_lookup(card: &Card, due: DateTime<Utc>, stage: DueStage) -> Document {
+        doc! {
+            "card": &card.id,
+            "due": Bson::UtcDatetime(due),
+            "stage": stage.key()
+        }
+    }
+
+    fn was_sent(&self, card: &Card, due: DateTime<Utc>, stage: DueStage) -> Result<bool, Box<Error>> {
+        let sent = self.store.find_one(SENT_REMINDERS_COLLECTION, DueDateJob::sent_lookup(card, due, stage))?;
+        Ok(sent.is_some())
+    }
+
+    fn record_sent(&self, card: &Card, due: DateTime<Utc>, stage: DueStage) -> Result<(), Box<Error>> {
+        self.store.insert_one(SENT_REMINDERS_COLLECTION, DueDateJob::sent_lookup(card, due, stage))?;
+        Ok(())
+    }
+}
+
+impl Job for DueDateJob {
+    fn name(&self) -> &str {
+        "due-dates"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::minutes(DUE_INTERVAL_MINUTES)
+    }
+
+    fn run(&self) -> Result<(), Box<Error>> {
+        let now = Utc::now();
+
+        let mut due_cards = Vec::new();
+        for card in self.board_handler.get_cards()? {
+            if card.due_complete {
+                continue;
+            }
+            if let Some(due) = card.due {
+                if let Some(stage) = DueStage::for_due_date(due, now) {
+                    if !self.was_sent(&card, due, stage)? {
+                        due_cards.push((card, due, stage));
+                    }
+                }
+            }
+        }
+
+        if due_cards.is_empty() {
+            return Ok(());
+        }
+
+        info!("Sending {} due date reminders.", due_cards.len());
+
+        let board = self.board_handler.get_board()?;
+        self.reminder.prefetch(&due_cards.iter().map(|&(ref card, _, _)| card).collect::<Vec<&Card>>())?;
+        for &(ref card, due, stage) in due_cards.iter() {
+            let variables = [
+                ("card", &card.name[..]),
+                ("url", &card.url),
+                ("board", &board.name)
+            ];
+            match self.reminder.send(card, stage.template(), &variables, &[("due", stage.due_template(), due)]) {
+                Ok(()) => self.record_sent(card, due, stage)?,
+                Err(e) => error!("Could not send a due date reminder about card {}: {}", card.id, e)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overdue_reminders_are_only_sent_for_a_day() {
+        let now = Utc::now();
+        let stage = |hours: i64| DueStage::for_due_date(now + Duration::hours(hours), now).map(|stage| stage.key());
+
+        assert_eq!(stage(48), None);
+        assert_eq!(stage(12), Some("day_before"));
+        assert_eq!(stage(1), Some("hour_before"));
+        assert_eq!(stage(0), Some("overdue"));
+        assert_eq!(stage(-23), Some("overdue"));
+        assert_eq!(stage(-24), None);
+        assert_eq!(stage(-24 * 30), None);
+    }
+}
@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::thread;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use store::Store;
+
+use bson::Bson;
+use chrono::prelude::*;
+use chrono::Duration;
+
+const JOBS_COLLECTION: &'static str = "jobs";
+const TICK_INTERVAL: u64 = 60;
+
+pub trait Job {
+    fn name(&self) -> &str;
+    fn interval(&self) -> Duration;
+    fn run(&self) -> Result<(), Box<Error>>;
+}
+
+pub struct Scheduler {
+    store: Arc<Store>,
+    jobs: Vec<Box<Job>>
+}
+
+impl Scheduler {
+    pub fn new(store: Arc<Store>) -> Scheduler {
+        Scheduler {
+            store: store,
+            jobs: Vec::new()
+        }
+    }
+
+    pub fn add_job(&mut self, job: Box<Job>) {
+        info!("Scheduling job \"{}\" every {} minutes.", job.name(), job.interval().num_minutes());
+        self.jobs.push(job);
+    }
+
+    // Last run times live in the database so that restarts don't reset (or skip) a weekly job
+    fn last_run(&self, job: &Job) -> Result<Option<DateTime<Utc>>, Box<Error>> {
+        let jdoc = self.store.find_one(JOBS_COLLECTION, doc! {
+            "name": job.name()
+        })?;
+        Ok(jdoc.and_then(|jdoc| jdoc.get_utc_datetime("last_run").ok().map(|date| *date)))
+    }
+
+    fn record_run(&self, job: &Job, date: DateTime<Utc>) -> Result<(), Box<Error>> {
+        self.store.update_one(JOBS_COLLECTION, doc! {
+            "name": job.name()
+        }, doc! {
+            "$set": { "last_run": Bson::UtcDatetime(date) }
+        }, true)?;
+
+        Ok(())
+    }
+
+    fn tick(&self) -> Result<(), Box<Error>> {
+        for job in self.jobs.iter() {
+            let now = Utc::now();
+            let due = match self.last_run(&**job)? {
+                Some(last_run) => now.signed_duration_since(last_run) >= job.interval(),
+                None => true
+            };
+
+            if due {
+                info!("Running job \"{}\" ...", job.name());
+
+                // A failing job is retried on its next interval rather than on every tick
+                if let Err(e) = job.run() {
+                    error!("Job \"{}\" failed: {}", job.name(), e);
+                }
+                self.record_run(&**job, now)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn run(&self) {
+        loop {
+            if let Err(e) = self.tick() {
+                error!("Scheduler error: {}", e);
+            }
+            thread::sleep(StdDuration::from_secs(TICK_INTERVAL));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
+
+use analytics::Analytics;
+use commands::CommandHandler;
+use history::EventStore;
+use links::Links;
+use memory_store::MemoryStore;
+use mock_board::MockBoard;
+use sinks::{NotificationSinks, SlackSink};
+use slack_fake::{FakeSlack, SentMessage};
+use slack_transport::{IncomingMessage, NotificationBuffer, NotificationSender, NOTIFICATION_BUFFER_SIZE};
+use supervisor::Supervisor;
+use templates::Templates;
+use trello::{BoardHandler, CardHandler};
+use trello_client::TrelloApi;
+use trello_fake::{FakeTrello, fixture};
+use trello_listeners::{HistoryListener, RelayActionListener};
+
+use reqwest::Method;
+use serde_json;
+use serde_json::Value;
+
+const BOARD_ID: &'static str = "5a0000000000000000000001";
+
+// Only Slack, since the other sinks would reach out to real servers
+fn simulation_sinks(notifications: NotificationSender) -> NotificationSinks {
+    let mut sinks = NotificationSinks::new();
+    sinks.add(Box::new(SlackSink::new(notifications)));
+    sinks
+}
+
+// Wires the command handler and the board listener up to fake Trello, fake Slack and the in-memory store,
+// so that a whole exchange of Slack commands and Trello changes can be scripted and every notification checked
+pub struct Simulation {
+    pub store: Arc<MemoryStore>,
+    pub trello: Arc<FakeTrello>,
+    pub slack: FakeSlack,
+    board: Arc<Mutex<MockBoard>>,
+    commands: CommandHandler,
+    board_handler: BoardHandler,
+    buffer: NotificationBuffer
+}
+
+impl Simulation {
+    pub fn new() -> Simulation {
+        // Board members carry their role with them, the way the mock board expects
+        let members = serde_json::from_str::<Vec<Value>>(&fixture("memberships.json")).unwrap().into_iter().map(|membership| {
+            let mut member = membership["member"].clone();
+            member["memberType"] = membership["memberType"].clone();
+            member
+        }).collect();
+        let board = Arc::new(Mutex::new(MockBoard::new(BOARD_ID, serde_json::from_str(&fixture("lists.json")).unwrap(), members)));
+
+        let store = Arc::new(MemoryStore::new());
+        let trello = Arc::new(FakeTrello::new());
+        let (tx, rx) = mpsc::sync_channel(NOTIFICATION_BUFFER_SIZE);
+        let notifications = NotificationSender::new(tx, Arc::new(AtomicUsize::new(0)));
+        let client : Arc<TrelloApi> = trello.clone();
+        let commands = CommandHandler::new(
+            store.clone(),
+            Analytics::new(EventStore::new(store.clone()), "Published", 14),
+            Supervisor::new().handle(),
+            BoardHandler::new(BOARD_ID, Arc::clone(&client)),
+            CardHandler::new(Arc::clone(&client)),
+            simulation_sinks(notifications.clone()),
+            Templates::defaults());
+        let card_handler = Arc::new(CardHandler::new(Arc::clone(&client)));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.add_listener(Box::new(HistoryListener::new(EventStore::new(store.clone()), Arc::clone(&card_handler))));
+        board_handler.add_listener(Box::new(RelayActionListener::new(store.clone(), card_handler, simulation_sinks(notifications),
+            Templates::defaults())));
+
+        let simulation = Simulation {
+            store: store,
+            trello: trello,
+            slack: FakeSlack::new(),
+            board: board,
+            commands: commands,
+            board_handler: board_handler,
+            buffer: NotificationBuffer::new(rx, Templates::defaults())
+        };
+        for resource in ["actions", "lists", "memberships", "cards"].iter() {
+            simulation.serve(Method::Get, &format!("/boards/{}/{}", BOARD_ID, resource));
+        }
+        simulation.serve(Method::Get, "/batch");
+        simulation
+    }
+
+    // FakeTrello matches requests by path, so every path the board should answer is handed to it one by one
+    fn serve(&self, method: Method, path: &str) {
+        let board = Arc::clone(&self.board);
+        let method_name = method.to_string();
+        self.trello.respond_with(method, path, move |route| board.lock().unwrap().handle(&method_name, route));
+    }
+
+    // Puts a card on the board and returns its ID
+    pub fn add_card(&self, short_link: &str, name: &str, list_name: &str, members: &[&str]) -> String {
+        let id = self.board.lock().unwrap().add_card(short_link, name, list_name, members).unwrap();
+        for card_ref in [&id[..], short_link].iter() {
+            self.serve(Method::Get, &format!("/cards/{}", card_ref));
+            self.serve(Method::Get, &format!("/cards/{}/actions", card_ref));
+        }
+        self.serve(Method::Put, &format!("/cards/{}", id));
+        id
+    }
+
+    // Sends a command from a user's DM and returns the replies
+    pub fn command(&self, user: &str, text: &str) -> Vec<String> {
+        let channel = format!("D{}", user);
+        let message = IncomingMessage {
+            user: user.to_string(),
+            channel: channel.clone(),
+            text: text.to_string()
+        };
+        self.commands.handle_message(&message, &self.slack).unwrap();
+
+        let replies = self.slack.take_messages();
+        assert!(replies.iter().all(|reply| reply.channel == channel));
+        replies.into_iter().map(|reply| reply.text).collect()
+    }
+
+    // Links a Slack user as if they had already commented their code from the Trello account
+    pub fn link(&self, user: &str, trello: &str) {
+        Links::new(self.store.clone()).confirm(user, trello).unwrap();
+    }
+
+    // Moves a card on Trello itself, as one of the board's members
+    pub fn move_card(&self, member: &str, card_ref: &str, list_name: &str) {
+        self.board.lock().unwrap().move_card(member, card_ref, list_name).unwrap();
+    }
+
+    pub fn comment(&self, member: &str, card_ref: &str, text: &str) {
+        self.board.lock().unwrap().comment(member, card_ref, text).unwrap();
+    }
+
+    // Runs one poll of the board and returns the notifications it delivered
+    pub fn poll(&mut self) -> Vec<SentMessage> {
+        self.board_handler.poll().unwrap();
+        self.buffer.flush(&self.slack);
+        self.slack.take_messages()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moved(channel: &str, card_id: &str, short_link: &str, writer: &str, text: &str) -> SentMessage {
+        let url = format!("https://trello.com/c/{}", short_link);
+        SentMessage {
+            channel: channel.to_string(),
+            text: text.to_string(),
+            blocks: Some(json!([
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": text }
+                },
+                {
+                    "type": "actions",
+                    "elements": [
+                        {
+                            "type": "button",
+                            "text": { "type": "plain_text", "text": "Untrack this writer" },
+                            "action_id": "untrack_writer",
+                            "value": writer
+                        },
+                        {
+                            "type": "button",
+                            "text": { "type": "plain_text", "text": "Mute this card" },
+                            "action_id": "mute_card",
+                            "value": card_id
+                        },
+                        {
+                            "type": "button",
+                            "text": { "type": "plain_text", "text": "Open in Trello" },
+                            "action_id": "open_card",
+                            "url": url
+                        }
+                    ]
+                }
+            ]))
+        }
+    }
+
+    #[test]
+    fn tracked_moves_are_delivered_once() {
+        let mut sim = Simulation::new();
+        let card = sim.add_card("LbRy0001", "Library hours extended", "Drafting", &["wendywriter"]);
+        sim.command("U1", "track wendywriter");
+
+        sim.move_card("eddieeditor", "LbRy0001", "Copy Editing");
+        assert_eq!(sim.poll(), vec![
+            moved("DU1", &card, "LbRy0001", "wendywriter", "Your card _Library hours extended_ has been moved from *Drafting* to *Copy Editing*.")
+        ]);
+        assert_eq!(sim.poll(), vec![]);
+    }
+
+    #[test]
+    fn every_tracker_of_every_member_is_notified() {
+        let mut sim = Simulation::new();
+        let card = sim.add_card("BdGt0002", "Campus budget explained", "Copy Editing", &["wendywriter", "fredfreelancer"]);
+        sim.command("U1", "track wendywriter");
+        sim.command("U2", "track fredfreelancer");
+        sim.command("U3", "track wendywriter");
+
+        sim.move_card("eddieeditor", "BdGt0002", "Published");
+        let text = "Your card _Campus budget explained_ has been moved from *Copy Editing* to *Published*.";
+        assert_eq!(sim.poll(), vec![
+            moved("DU1", &card, "BdGt0002", "wendywriter", text),
+            moved("DU3", &card, "BdGt0002", "wendywriter", text),
+            moved("DU2", &card, "BdGt0002", "fredfreelancer", text)
+        ]);
+    }
+
+    #[test]
+    fn muted_cards_stay_quiet_until_unmuted() {
+        let mut sim = Simulation::new();
+        sim.add_card("LbRy0001", "Library hours extended", "Drafting", &["wendywriter"]);
+        let other_card = sim.add_card("BdGt0002", "Campus budget explained", "Drafting", &["wendywriter"]);
+        sim.command("U1", "track wendywriter");
+        sim.command("U1", "mute LbRy0001");
+
+        sim.move_card("eddieeditor", "LbRy0001", "Copy Editing");
+        sim.move_card("eddieeditor", "BdGt0002", "Copy Editing");
+        assert_eq!(sim.poll(), vec![
+            moved("DU1", &other_card, "BdGt0002", "wendywriter", "Your card _Campus budget explained_ has been moved from *Drafting* to *Copy Editing*.")
+        ]);
+
+        sim.command("U1", "unmute LbRy0001");
+        sim.move_card("eddieeditor", "LbRy0001", "Published");
+        assert_eq!(sim.poll().len(), 1);
+    }
+
+    #[test]
+    fn untracked_and_switched_writers_are_not_notified() {
+        let mut sim = Simulation::new();
+        let card = sim.add_card("PlCy0003", "Parking policy", "Drafting", &["fredfreelancer"]);
+        sim.add_card("LbRy0001", "Library hours extended", "Drafting", &["wendywriter"]);
+        sim.command("U1", "track wendywriter");
+        sim.command("U1", "track fredfreelancer");
+
+        sim.move_card("eddieeditor", "LbRy0001", "Copy Editing");
+        sim.move_card("eddieeditor", "PlCy0003", "Copy Editing");
+        assert_eq!(sim.poll(), vec![
+            moved("DU1", &card, "PlCy0003", "fredfreelancer", "Your card _Parking policy_ has been moved from *Drafting* to *Copy Editing*.")
+        ]);
+
+        sim.command("U1", "untrack");
+        sim.move_card("eddieeditor", "PlCy0003", "Published");
+        assert_eq!(sim.poll(), vec![]);
+    }
+
+    #[test]
+    fn comments_and_untracked_cards_are_only_recorded() {
+        let mut sim = Simulation::new();
+        sim.add_card("LbRy0001", "Library hours extended", "Drafting", &["wendywriter"]);
+        sim.add_card("PlCy0003", "Parking policy", "Drafting", &["fredfreelancer"]);
+        sim.command("U1", "track wendywriter");
+
+        sim.comment("eddieeditor", "LbRy0001", "Needs a second source.");
+        sim.move_card("eddieeditor", "PlCy0003", "Copy Editing");
+        assert_eq!(sim.poll(), vec![]);
+
+        // Comments aren't asked for at all, while the move is kept without anyone to notify
+        let events = sim.store.all("events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_str("type").unwrap(), "updateCard");
+        assert!(events[0].get_array("recipients").unwrap().is_empty());
+    }
+
+    #[test]
+    fn moves_made_from_slack_come_back_as_notifications() {
+        let mut sim = Simulation::new();
+        let card = sim.add_card("LbRy0001", "Library hours extended", "Drafting", &["wendywriter"]);
+        sim.command("U1", "track wendywriter");
+        sim.link("U2", "eddieeditor");
+
+        assert_eq!(sim.command("U2", "move LbRy0001 to copy editing"), vec!["Moved _Library hours extended_ to *Copy Editing*."]);
+        assert_eq!(sim.poll(), vec![
+            moved("DU1", &card, "LbRy0001", "wendywriter", "Your card _Library hours extended_ has been moved from *Drafting* to *Copy Editing*.")
+        ]);
+
+        let history = sim.command("U1", "history LbRy0001");
+        assert_eq!(history.len(), 1);
+        assert!(history[0].starts_with("History of _Library hours extended_:\n• "));
+        assert!(history[0].ends_with(": moved from *Drafting* to *Copy Editing* by articlebot (notified <@U1>)"));
+    }
+}
@@ -0,0 +1,384 @@
+use std::error::Error;
+use std::net::{IpAddr, ToSocketAddrs};
+
+use slack_transport::{Notification, NotificationSender};
+use templates::Templates;
+
+use lettre::EmailTransport;
+use lettre::smtp::SmtpTransport;
+use lettre::smtp::authentication::Credentials;
+use lettre_email::EmailBuilder;
+use reqwest::{Client, RedirectPolicy};
+use serde_json::Value;
+use url::{Host, Url};
+
+// Somewhere a notification can be delivered to. Subscriptions pick a sink by name and give it a target,
+// such as a Slack channel, an email address or a webhook URL.
+pub trait NotificationSink {
+    fn name(&self) -> &str;
+    // Catches obvious mistakes in a target before a subscription to it is saved, with the template explaining what's wrong
+    fn check_target(&self, target: &str) -> Result<(), &'static str>;
+    fn deliver(&self, target: &str, notification: &Notification) -> Result<(), Box<Error>>;
+}
+
+// Hands notifications to the Slack transport, which sends them the next time it flushes its buffer
+pub struct SlackSink {
+    notifications: NotificationSender
+}
+
+impl SlackSink {
+    pub fn new(notifications: NotificationSender) -> SlackSink {
+        SlackSink {
+            notifications: notifications
+        }
+    }
+}
+
+impl NotificationSink for SlackSink {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    fn check_target(&self, target: &str) -> Result<(), &'static str> {
+        if target.starts_with('C') || target.starts_with('G') || target.starts_with('D') {
+            Ok(())
+        }
+        else {
+            Err("target.not_a_slack_channel")
+        }
+    }
+
+    fn deliver(&self, target: &str, notification: &Notification) -> Result<(), Box<Error>> {
+        let mut notification = notification.clone();
+        notification.channel = target.to_string();
+        self.notifications.send(notification)
+    }
+}
+
+pub struct EmailSink {
+    host: String,
+    credentials: Option<Credentials>,
+    from: String,
+    templates: Templates
+}
+
+impl EmailSink {
+    pub fn new(host: &str, credentials: Option<(String, String)>, from: &str, templates: Templates) -> EmailSink {
+        EmailSink {
+            host: host.to_string(),
+            credentials: credentials.map(|(username, password)| Credentials::new(username, password)),
+            from: from.to_string(),
+            templates: templates
+        }
+    }
+}
+
+impl NotificationSink for EmailSink {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    fn check_target(&self, target: &str) -> Result<(), &'static str> {
+        if target.contains('@') && !target.contains(' ') {
+            Ok(())
+        }
+        else {
+            Err("target.not_an_email_address")
+        }
+    }
+
+    fn deliver(&self, target: &str, notification: &Notification) -> Result<(), Box<Error>> {
+        let email = EmailBuilder::new()
+            .to(target)
+            .from(&self.from[..])
+            .subject(self.templates.in_language(&notification.language).render("email.subject", &[]))
+            .text(plain_text(notification))
+            .build()?;
+
+        let mut builder = SmtpTransport::simple_builder(&self.host)?;
+        if let Some(ref credentials) = self.credentials {
+            builder = builder.credentials(credentials.clone());
+        }
+        let mut mailer = builder.build();
+        mailer.send(&email)?;
+        Ok(())
+    }
+}
+
+// Addresses that only make sense inside our own network, which a webhook must never be pointed at
+fn is_internal_address(address: &IpAddr) -> bool {
+    match *address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+            address.is_private() || address.is_loopback() || address.is_link_local() || address.is_unspecified() ||
+                address.is_broadcast() || octets[0] == 0 || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(address) => {
+            // IPv4 addresses written as IPv6 ones, including :: and ::1, are judged as IPv4
+            if let Some(address) = address.to_ipv4() {
+                return is_internal_address(&IpAddr::V4(address));
+            }
+            // fc00::/7 is unique local, fe80::/10 is link-local
+            let first = address.segments()[0];
+            first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+// Webhooks are posted to from the bot's own host, so only HTTPS URLs that resolve to public addresses are accepted
+fn check_public_url(target: &str) -> Result<(), &'static str> {
+    let not_public = || Err("target.not_public");
+    let url = match Url::parse(target) {
+        Ok(ref url) if url.scheme() == "https" => url.clone(),
+        _ => return not_public()
+    };
+
+    let addresses : Vec<IpAddr> = match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.to_lowercase();
+            if domain == "localhost" || [".localhost", ".local", ".internal"].iter().any(|suffix| domain.ends_with(suffix)) {
+                return not_public();
+            }
+            match (&domain[..], url.port_or_known_default().unwrap_or(443)).to_socket_addrs() {
+                Ok(addresses) => addresses.map(|address| address.ip()).collect(),
+                Err(_) => return Err("target.not_found")
+            }
+        }
+        Some(Host::Ipv4(address)) => vec![IpAddr::V4(address)],
+        Some(Host::Ipv6(address)) => vec![IpAddr::V6(address)],
+        None => return not_public()
+    };
+    if addresses.is_empty() || addresses.iter().any(is_internal_address) {
+        return not_public();
+    }
+    Ok(())
+}
+
+fn check_discord_url(target: &str) -> Result<(), &'static str> {
+    if ["https://discord.com/api/webhooks/", "https://discordapp.com/api/webhooks/"].iter().any(|prefix| target.starts_with(prefix)) {
+        Ok(())
+    }
+    else {
+        Err("target.not_a_discord_webhook")
+    }
+}
+
+fn check_teams_url(target: &str) -> Result<(), &'static str> {
+    match Url::parse(target) {
+        Ok(ref url) if url.scheme() == "https" && url.host_str().map(|host| host.ends_with(".webhook.office.com")).unwrap_or(false) => Ok(()),
+        _ => Err("target.not_a_teams_webhook")
+    }
+}
+
+// Discord, Teams and generic webhooks only differ in what they expect to be posted to them, and where they can be posted
+pub struct WebhookSink {
+    name: &'static str,
+    payload: fn(&Notification, &Templates) -> Value,
+    check: fn(&str) -> Result<(), &'static str>,
+    templates: Templates,
+    http_client: Client
+}
+
+impl WebhookSink {
+    pub fn discord(templates: Templates) -> WebhookSink {
+        WebhookSink::new("discord", discord_payload, check_discord_url, templates)
+    }
+
+    pub fn teams(templates: Templates) -> WebhookSink {
+        WebhookSink::new("teams", teams_payload, check_teams_url, templates)
+    }
+
+    pub fn webhook(templates: Templates) -> WebhookSink {
+        WebhookSink::new("webhook", webhook_payload, check_public_url, templates)
+    }
+
+    fn new(name: &'static str, payload: fn(&Notification, &Templates) -> Value, check: fn(&str) -> Result<(), &'static str>,
+           templates: Templates) -> WebhookSink {
+        WebhookSink {
+            name: name,
+            payload: payload,
+            check: check,
+            templates: templates,
+            // A redirect could send the post somewhere the target check would have turned down
+            http_client: Client::builder().redirect(RedirectPolicy::none()).build().expect("Could not set up the webhook client")
+        }
+    }
+}
+
+impl NotificationSink for WebhookSink {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn check_target(&self, target: &str) -> Result<(), &'static str> {
+        (self.check)(target)
+    }
+
+    fn deliver(&self, target: &str, notification: &Notification) -> Result<(), Box<Error>> {
+        // Checked again, since subscriptions may predate the checks and a host may have moved since it was checked
+        if let Err(reason) = self.check_target(target) {
+            return Err(From::from(self.templates.render(reason, &[("target", target)])));
+        }
+
+        let resp = self.http_client.post(target).json(&(self.payload)(notification, &self.templates)).send()?;
+        if !resp.status().is_success() {
+            return Err(From::from(format!("The {} webhook responded with {}", self.name, resp.status())));
+        }
+        Ok(())
+    }
+}
+
+// Slack's *bold* and _italics_ don't mean the same thing everywhere, so only the words are kept
+fn plain_text(notification: &Notification) -> String {
+    let mut text = notification.text.replace("*", "").replace("_", "");
+    if let Some(ref card) = notification.card {
+        text.push_str(&format!("\n\n{}", card.url));
+    }
+    text
+}
+
+fn discord_payload(notification: &Notification, _templates: &Templates) -> Value {
+    // Discord reads *text* as italics, so bold needs doubling up
+    let mut content = notification.text.replace("*", "**");
+    if let Some(ref card) = notification.card {
+        content.push_str(&format!("\n<{}>", card.url));
+    }
+    json!({
+        "username": "articlebot",
+        "content": content
+    })
+}
+
+fn teams_payload(notification: &Notification, templates: &Templates) -> Value {
+    let mut payload = json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "summary": plain_text(notification),
+        "text": notification.text.replace("*", "**")
+    });
+    if let Some(ref card) = notification.card {
+        payload["potentialAction"] = json!([
+            {
+                "@type": "OpenUri",
+                "name": templates.in_language(&notification.language).render("button.open", &[]),
+                "targets": [{ "os": "default", "uri": &card.url }]
+            }
+        ]);
+    }
+    payload
+}
+
+fn webhook_payload(notification: &Notification, _templates: &Templates) -> Value {
+    json!({
+        "text": &notification.text,
+        "card": notification.card.as_ref().map(|card| json!({
+            "id": &card.id,
+            "url": &card.url,
+            "writer": &card.writer
+        }))
+    })
+}
+
+// Every sink articlebot was configured with, looked up by name
+pub struct NotificationSinks {
+    sinks: Vec<Box<NotificationSink>>
+}
+
+impl NotificationSinks {
+    pub fn new() -> NotificationSinks {
+        NotificationSinks {
+            sinks: Vec::new()
+        }
+    }
+
+    pub fn add(&mut self, sink: Box<NotificationSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NotificationSink> {
+        self.sinks.iter().find(|sink| sink.name() == name).map(|sink| &**sink)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.sinks.iter().map(|sink| sink.name()).collect()
+    }
+
+    pub fn deliver(&self, name: &str, target: &str, notification: &Notification) -> Result<(), Box<Error>> {
+        match self.get(name) {
+            Some(sink) => sink.deliver(target, notification),
+            None => Err(From::from(format!("There is no {} sink", name)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slack_transport::NotificationCard;
+
+    fn notification() -> Notification {
+        Notification {
+            channel: String::new(),
+            text: "Your card _Library hours extended_ has been moved from *Drafting* to *Copy Editing*.".to_string(),
+            language: "en".to_string(),
+            card: Some(NotificationCard {
+                id: "5a3000000000000000000001".to_string(),
+                url: "https://trello.com/c/CrD1sHrt".to_string(),
+                writer: "wendywriter".to_string(),
+                snooze: false
+            })
+        }
+    }
+
+    #[test]
+    fn email_is_sent_as_plain_text() {
+        assert_eq!(plain_text(&notification()),
+            "Your card Library hours extended has been moved from Drafting to Copy Editing.\n\nhttps://trello.com/c/CrD1sHrt");
+    }
+
+    #[test]
+    fn discord_gets_markdown_and_a_link() {
+        assert_eq!(discord_payload(&notification(), &Templates::defaults())["content"],
+            "Your card _Library hours extended_ has been moved from **Drafting** to **Copy Editing**.\n<https://trello.com/c/CrD1sHrt>");
+    }
+
+    #[test]
+    fn teams_gets_a_message_card_with_a_button() {
+        let payload = teams_payload(&notification(), &Templates::defaults());
+        assert_eq!(payload["@type"], "MessageCard");
+        assert_eq!(payload["potentialAction"][0]["name"], "Open in Trello");
+        assert_eq!(payload["potentialAction"][0]["targets"][0]["uri"], "https://trello.com/c/CrD1sHrt");
+
+        let mut notification = notification();
+        notification.language = "es".to_string();
+        assert_eq!(teams_payload(&notification, &Templates::defaults())["potentialAction"][0]["name"], "Abrir en Trello");
+    }
+
+    #[test]
+    fn webhooks_get_the_card_alongside_the_text() {
+        let payload = webhook_payload(&notification(), &Templates::defaults());
+        assert_eq!(payload["text"], notification().text);
+        assert_eq!(payload["card"]["writer"], "wendywriter");
+    }
+
+    #[test]
+    fn targets_are_checked_against_their_sink() {
+        assert!(WebhookSink::discord(Templates::defaults()).check_target("https://discord.com/api/webhooks/1/abc").is_ok());
+        assert_eq!(WebhookSink::discord(Templates::defaults()).check_target("https://example.com/api/webhooks/1/abc"), Err("target.not_a_discord_webhook"));
+        assert!(WebhookSink::teams(Templates::defaults()).check_target("https://newsroom.webhook.office.com/webhookb2/abc").is_ok());
+        assert!(WebhookSink::teams(Templates::defaults()).check_target("http://newsroom.webhook.office.com/webhookb2/abc").is_err());
+        assert!(WebhookSink::teams(Templates::defaults()).check_target("fred@example.com").is_err());
+        assert!(EmailSink::new("smtp.example.com", None, "articlebot@example.com", Templates::defaults()).check_target("fred@example.com").is_ok());
+        assert!(EmailSink::new("smtp.example.com", None, "articlebot@example.com", Templates::defaults()).check_target("fredfreelancer").is_err());
+    }
+
+    #[test]
+    fn webhooks_cannot_reach_internal_hosts() {
+        for target in ["http://203.0.113.7/hook", "https://localhost/hook", "https://printer.local/hook", "https://127.0.0.1/hook",
+                       "https://169.254.169.254/latest/meta-data", "https://10.0.0.8/hook", "https://192.168.1.1/hook", "https://[::1]/hook",
+                       "https://[fd00::1]/hook", "https://[::ffff:127.0.0.1]/hook", "not a url"].iter() {
+            assert!(WebhookSink::webhook(Templates::defaults()).check_target(target).is_err(), "{} was accepted", target);
+        }
+        assert!(WebhookSink::webhook(Templates::defaults()).check_target("https://203.0.113.7/hook").is_ok());
+    }
+}
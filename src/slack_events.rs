@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use commands::CommandHandler;
+use slack_http::SlackHttpServer;
+use slack_transport::{SlackTransport, NotificationBuffer};
+
+const POLL_INTERVAL_MILLIS: u64 = 1000;
+
+pub struct EventsApiTransport {
+    address: String,
+    signing_secret: String,
+    api_key: String
+}
+
+impl EventsApiTransport {
+    pub fn new(address: &str, signing_secret: &str, api_key: &str) -> EventsApiTransport {
+        EventsApiTransport {
+            address: address.to_string(),
+            signing_secret: signing_secret.to_string(),
+            api_key: api_key.to_string()
+        }
+    }
+}
+
+impl SlackTransport for EventsApiTransport {
+    fn run(&mut self, command_handler: &CommandHandler, buffer: &mut NotificationBuffer) {
+        let server = SlackHttpServer::new(&self.address, &self.signing_secret, &self.api_key, true);
+
+        loop {
+            server.serve(command_handler, Duration::from_millis(POLL_INTERVAL_MILLIS));
+
+            // There is no bot message to piggyback on here, so the buffer is flushed between requests
+            buffer.flush(server.output());
+        }
+    }
+}
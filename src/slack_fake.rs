@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+use slack_transport::{SlackOutput, SlackUser};
+
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentMessage {
+    pub channel: String,
+    pub text: String,
+    pub blocks: Option<Value>
+}
+
+// Records everything sent to Slack instead of sending it. DMs are opened as "D" followed by the user ID.
+pub struct FakeSlack {
+    messages: Mutex<Vec<SentMessage>>,
+    opened_dms: Mutex<Vec<String>>,
+    users: Mutex<HashMap<String, String>>,
+    unreachable: Mutex<Vec<String>>
+}
+
+impl FakeSlack {
+    pub fn new() -> FakeSlack {
+        FakeSlack {
+            messages: Mutex::new(Vec::new()),
+            opened_dms: Mutex::new(Vec::new()),
+            users: Mutex::new(HashMap::new()),
+            unreachable: Mutex::new(Vec::new())
+        }
+    }
+
+    pub fn add_user(&self, id: &str, name: &str) {
+        self.users.lock().unwrap().insert(id.to_string(), name.to_string());
+    }
+
+    // Anything sent to the channel from now on fails, as if Slack had turned it down
+    pub fn make_unreachable(&self, channel: &str) {
+        self.unreachable.lock().unwrap().push(channel.to_string());
+    }
+
+    pub fn messages(&self) -> Vec<SentMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    // Returns the messages sent since the last call, so each step of a test only sees its own replies
+    pub fn take_messages(&self) -> Vec<SentMessage> {
+        self.messages.lock().unwrap().drain(..).collect()
+    }
+
+    pub fn opened_dms(&self) -> Vec<String> {
+        self.opened_dms.lock().unwrap().clone()
+    }
+}
+
+impl SlackOutput for FakeSlack {
+    fn send_message(&self, channel: &str, text: &str) -> Result<(), Box<Error>> {
+        if self.unreachable.lock().unwrap().iter().any(|unreachable| unreachable == channel) {
+            return Err(From::from(format!("channel_not_found: {}", channel)));
+        }
+        self.messages.lock().unwrap().push(SentMessage {
+            channel: channel.to_string(),
+            text: text.to_string(),
+            blocks: None
+        });
+        Ok(())
+    }
+
+    fn send_blocks(&self, channel: &str, text: &str, blocks: &Value) -> Result<(), Box<Error>> {
+        self.messages.lock().unwrap().push(SentMessage {
+            channel: channel.to_string(),
+            text: text.to_string(),
+            blocks: Some(blocks.clone())
+        });
+        Ok(())
+    }
+
+    fn open_dm(&self, user: &str) -> Result<String, Box<Error>> {
+        self.opened_dms.lock().unwrap().push(user.to_string());
+        Ok(format!("D{}", user))
+    }
+
+    fn lookup_user(&self, user: &str) -> Result<Option<SlackUser>, Box<Error>> {
+        Ok(self.users.lock().unwrap().get(user).map(|name| SlackUser {
+            id: user.to_string(),
+            name: name.clone(),
+            real_name: String::new(),
+            is_bot: false
+        }))
+    }
+}
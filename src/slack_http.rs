@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::Duration;
+
+use commands::CommandHandler;
+use slack_transport::{IncomingMessage, addressed_text};
+use slack_web::{WebApiOutput, ResponseUrlOutput};
+
+use chrono::prelude::*;
+use hex;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use serde_json;
+use serde_json::Value;
+use tiny_http::{Server, Request, Response, Header};
+use url::form_urlencoded;
+
+const EVENTS_PATH: &'static str = "/slack/events";
+const COMMANDS_PATH: &'static str = "/slack/commands";
+const INTERACTIVITY_PATH: &'static str = "/slack/interactivity";
+const SIGNATURE_VERSION: &'static str = "v0";
+// Slack recommends rejecting requests older than five minutes to prevent replay attacks
+const MAX_REQUEST_AGE_SECS: i64 = 300;
+
+pub struct RequestVerifier {
+    signing_secret: String
+}
+
+impl RequestVerifier {
+    pub fn new(signing_secret: &str) -> RequestVerifier {
+        RequestVerifier {
+            signing_secret: signing_secret.to_string()
+        }
+    }
+
+    pub fn verify(&self, request: &Request, body: &str) -> bool {
+        let timestamp = match header_value(request, "X-Slack-Request-Timestamp") {
+            Some(timestamp) => timestamp,
+            None => return false
+        };
+        let signature = match header_value(request, "X-Slack-Signature") {
+            Some(signature) => signature,
+            None => return false
+        };
+
+        match timestamp.parse::<i64>() {
+            Ok(seconds) if (Utc::now().timestamp() - seconds).abs() <= MAX_REQUEST_AGE_SECS => {},
+            _ => return false
+        }
+
+        let expected = match hex::decode(signature.trim_start_matches(&format!("{}=", SIGNATURE_VERSION)[..])) {
+            Ok(expected) => expected,
+            Err(_) => return false
+        };
+
+        let mut mac = Hmac::<Sha256>::new_varkey(self.signing_secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.input(format!("{}:{}:{}", SIGNATURE_VERSION, timestamp, body).as_bytes());
+        mac.verify(&expected).is_ok()
+    }
+}
+
+fn header_value(request: &Request, name: &'static str) -> Option<String> {
+    request.headers().iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().to_string())
+}
+
+fn respond(request: Request, status: u16, body: &str) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap();
+    let response = Response::from_string(body).with_status_code(status).with_header(header);
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to Slack: {}", e);
+    }
+}
+
+fn parse_form(body: &str) -> HashMap<String, String> {
+    form_urlencoded::parse(body.as_bytes()).into_owned().collect()
+}
+
+pub struct SlackHttpServer {
+    server: Server,
+    verifier: RequestVerifier,
+    output: WebApiOutput,
+    bot_user_id: String,
+    // With the RTM transport, messages arrive over the websocket, so any events delivered here are ignored
+    handle_events: bool
+}
+
+impl SlackHttpServer {
+    pub fn new(address: &str, signing_secret: &str, api_key: &str, handle_events: bool) -> SlackHttpServer {
+        let output = WebApiOutput::new(api_key);
+        let bot_user_id = output.bot_user_id().expect("Slack authentication error");
+
+        let server = Server::http(address).expect("Slack HTTP server error");
+        info!("Listening for Slack requests on {}.", address);
+
+        SlackHttpServer {
+            server: server,
+            verifier: RequestVerifier::new(signing_secret),
+            output: output,
+            bot_user_id: bot_user_id,
+            handle_events: handle_events
+        }
+    }
+
+    pub fn output(&self) -> &WebApiOutput {
+        &self.output
+    }
+
+    pub fn serve(&self, command_handler: &CommandHandler, timeout: Duration) {
+        match self.server.recv_timeout(timeout) {
+            Ok(Some(request)) => self.handle_request(request, command_handler),
+            Ok(None) => {},
+            Err(e) => error!("Slack HTTP server error: {}", e)
+        }
+    }
+
+    fn handle_request(&self, mut request: Request, command_handler: &CommandHandler) {
+        let mut body = String::new();
+        if let Err(e) = request.as_reader().read_to_string(&mut body) {
+            warn!("Failed to read Slack request body: {}", e);
+            return respond(request, 400, "");
+        }
+
+        if !self.verifier.verify(&request, &body) {
+            warn!("Rejected Slack request with an invalid signature.");
+            return respond(request, 401, "");
+        }
+
+        let path = request.url().to_string();
+        if path == EVENTS_PATH && self.handle_events {
+            self.handle_event_request(request, &body, command_handler);
+        }
+        else if path == COMMANDS_PATH {
+            self.handle_command_request(request, &body, command_handler);
+        }
+        else if path == INTERACTIVITY_PATH {
+            self.handle_interactivity_request(request, &body, command_handler);
+        }
+        else {
+            respond(request, 404, "");
+        }
+    }
+
+    fn handle_event_request(&self, request: Request, body: &str, command_handler: &CommandHandler) {
+        let payload : Value = match serde_json::from_str(body) {
+            Ok(payload) => payload,
+            Err(_) => return respond(request, 400, "")
+        };
+
+        match payload.get("type").and_then(|t| t.as_str()) {
+            Some("url_verification") => {
+                let challenge = payload.get("challenge").and_then(|c| c.as_str()).unwrap_or("");
+                respond(request, 200, challenge);
+            }
+            Some("event_callback") => {
+                // Acknowledge right away, since Slack retries any event that isn't acknowledged within three seconds
+                respond(request, 200, "");
+                if let Some(event) = payload.get("event") {
+                    self.handle_event(event, command_handler);
+                }
+            }
+            _ => respond(request, 200, "")
+        }
+    }
+
+    fn handle_event(&self, event: &Value, command_handler: &CommandHandler) {
+        if event.get("type").and_then(|t| t.as_str()) != Some("message") {
+            return;
+        }
+
+        // Edits, joins and bot messages (including our own) all carry a subtype or a bot ID
+        if event.get("subtype").is_some() || event.get("bot_id").is_some() {
+            return;
+        }
+
+        let field = |name: &str| event.get(name).and_then(|value| value.as_str()).map(|value| value.to_string());
+        if let (Some(user), Some(channel), Some(text)) = (field("user"), field("channel"), field("text")) {
+            if user == self.bot_user_id {
+                return;
+            }
+
+            let is_direct = field("channel_type").map_or(false, |channel_type| channel_type == "im");
+            let text = match addressed_text(&text, is_direct, &self.bot_user_id) {
+                Some(text) => text,
+                None => return
+            };
+
+            let incoming = IncomingMessage {
+                user: user,
+                channel: channel,
+                text: text
+            };
+            if let Err(e) = command_handler.handle_message(&incoming, &self.output) {
+                error!("Failed to handle command: {}", e);
+            }
+        }
+    }
+
+    fn handle_command_request(&self, request: Request, body: &str, command_handler: &CommandHandler) {
+        let form = parse_form(body);
+        respond(request, 200, "");
+
+        let (user, response_url) = match (form.get("user_id"), form.get("response_url")) {
+            (Some(user), Some(response_url)) => (user, response_url),
+            _ => {
+                warn!("Slash command is missing its user or response URL.");
+                return;
+            }
+        };
+
+        // `/articlebot` on its own is treated the same as `/articlebot help`
+        let text = form.get("text").map(|text| text.trim()).unwrap_or("");
+        let text = if text.is_empty() { "help" } else { text };
+
+        self.dispatch(user, text, response_url, command_handler);
+    }
+
+    fn handle_interactivity_request(&self, request: Request, body: &str, command_handler: &CommandHandler) {
+        let form = parse_form(body);
+        respond(request, 200, "");
+
+        let payload : Value = match form.get("payload").and_then(|payload| serde_json::from_str(payload).ok()) {
+            Some(payload) => payload,
+            None => {
+                warn!("Interaction is missing its payload.");
+                return;
+            }
+        };
+
+        let user = payload.get("user").and_then(|u| u.get("id")).and_then(|id| id.as_str());
+        let response_url = payload.get("response_url").and_then(|r| r.as_str());
+        let (user, response_url) = match (user, response_url) {
+            (Some(user), Some(response_url)) => (user, response_url),
+            _ => {
+                warn!("Interaction is missing its user or response URL.");
+                return;
+            }
+        };
+
+        let actions = payload.get("actions").and_then(|a| a.as_array()).cloned().unwrap_or(Vec::new());
+        for action in actions {
+            let action_id = action.get("action_id").and_then(|a| a.as_str()).unwrap_or("");
+            let value = action.get("value").and_then(|v| v.as_str()).unwrap_or("");
+
+            // Each button maps onto the text command that does the same thing
+            let text = match action_id {
+                "untrack_writer" => format!("untrack {}", value),
+                "mute_card" => format!("mute {}", value),
+                "snooze_card" => format!("snooze {}", value),
+                // Link buttons are opened by Slack itself, but it still lets us know they were clicked
+                "open_card" => continue,
+                other => {
+                    warn!("Unknown interaction \"{}\".", other);
+                    continue;
+                }
+            };
+
+            self.dispatch(user, &text, response_url, command_handler);
+        }
+    }
+
+    fn dispatch(&self, user: &str, text: &str, response_url: &str, command_handler: &CommandHandler) {
+        // Commands remember the channel they were given so notifications have somewhere to go,
+        // so point them at the user's DM rather than whichever channel the command was typed in
+        let channel = match self.output.open_dm(user) {
+            Ok(channel) => channel,
+            Err(e) => {
+                error!("Failed to open DM with {}: {}", user, e);
+                return;
+            }
+        };
+
+        let incoming = IncomingMessage {
+            user: user.to_string(),
+            channel: channel,
+            text: text.to_string()
+        };
+        if let Err(e) = command_handler.handle_message(&incoming, &ResponseUrlOutput::new(response_url, &self.output)) {
+            error!("Failed to handle command: {}", e);
+        }
+    }
+}
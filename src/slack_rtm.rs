@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+use std::{env, thread, cmp};
+
+use commands::CommandHandler;
+use slack_transport::{SlackOutput, SlackUser, SlackTransport, IncomingMessage, NotificationBuffer, addressed_text};
+use slack_web::WebApiOutput;
+
+use serde_json::Value;
+use slack;
+use slack::{Event, EventHandler, RtmClient, Message};
+
+const RECONNECT_BACKOFF_BASE: u64 = 1;
+const RECONNECT_BACKOFF_MAX: u64 = 60;
+const STABLE_CONNECTION_SECS: u64 = 60;
+
+// Messages go out over the RTM connection, but RTM can't send blocks, open DMs or look people up, so that goes through the Web API
+struct RtmOutput<'a> {
+    sender: &'a slack::Sender,
+    web: &'a WebApiOutput
+}
+
+impl<'a> SlackOutput for RtmOutput<'a> {
+    fn send_message(&self, channel: &str, text: &str) -> Result<(), Box<Error>> {
+        self.sender.send_message(channel, text)?;
+        Ok(())
+    }
+
+    fn send_blocks(&self, channel: &str, text: &str, blocks: &Value) -> Result<(), Box<Error>> {
+        self.web.send_blocks(channel, text, blocks)
+    }
+
+    fn open_dm(&self, user: &str) -> Result<String, Box<Error>> {
+        self.web.open_dm(user)
+    }
+
+    fn lookup_user(&self, user: &str) -> Result<Option<SlackUser>, Box<Error>> {
+        self.web.lookup_user(user)
+    }
+}
+
+struct SlackHandler<'a> {
+    command_handler: &'a CommandHandler,
+    buffer: &'a mut NotificationBuffer,
+    web: &'a WebApiOutput,
+    connected_at: Option<Instant>,
+    bot_user_id: Option<String>
+}
+
+impl<'a> EventHandler for SlackHandler<'a> {
+    fn on_event(&mut self, cli: &RtmClient, event: Event) {
+        let output = RtmOutput {
+            sender: cli.sender(),
+            web: self.web
+        };
+
+        if let Event::Message(boxed_message) = event {
+            if let Message::Standard(message) = *boxed_message {
+                // Apps posting as a bot user still come through as standard messages
+                if message.bot_id.is_some() {
+                    return;
+                }
+                let bot_user_id = match self.bot_user_id {
+                    Some(ref bot_user_id) => bot_user_id.clone(),
+                    None => return
+                };
+                if let (Some(user), Some(channel), Some(text)) = (message.user, message.channel, message.text) {
+                    if user == bot_user_id {
+                        return;
+                    }
+
+                    // DM channel IDs always start with a D
+                    let text = match addressed_text(&text, channel.starts_with("D"), &bot_user_id) {
+                        Some(text) => text,
+                        None => return
+                    };
+
+                    let incoming = IncomingMessage {
+                        user: user,
+                        channel: channel,
+                        text: text
+                    };
+                    if let Err(e) = self.command_handler.handle_message(&incoming, &output) {
+                        error!("Failed to handle command: {}", e);
+                    }
+                }
+            }
+            else if let Message::BotMessage(_) = *boxed_message {
+                self.buffer.flush(&output);
+            }
+        }
+    }
+
+    fn on_connect(&mut self, cli: &RtmClient) {
+        info!("v{} connected.", env::var("CARGO_PKG_VERSION").unwrap());
+        self.connected_at = Some(Instant::now());
+        self.bot_user_id = cli.start_response().slf.as_ref().and_then(|slf| slf.id.clone());
+
+        // Deliver anything that piled up while we were disconnected
+        self.buffer.flush(&RtmOutput {
+            sender: cli.sender(),
+            web: self.web
+        });
+    }
+
+    fn on_close(&mut self, _cli: &RtmClient) {
+        info!("v{} disconnected.", env::var("CARGO_PKG_VERSION").unwrap());
+    }
+}
+
+pub struct RtmTransport {
+    api_key: String,
+    web: WebApiOutput
+}
+
+impl RtmTransport {
+    pub fn new(api_key: &str) -> RtmTransport {
+        RtmTransport {
+            api_key: api_key.to_string(),
+            web: WebApiOutput::new(api_key)
+        }
+    }
+}
+
+impl SlackTransport for RtmTransport {
+    fn run(&mut self, command_handler: &CommandHandler, buffer: &mut NotificationBuffer) {
+        let mut slack_handler = SlackHandler {
+            command_handler: command_handler,
+            buffer: buffer,
+            web: &self.web,
+            connected_at: None,
+            bot_user_id: None
+        };
+
+        let mut reconnect_attempt = 0;
+        loop {
+            slack_handler.connected_at = None;
+            match RtmClient::login_and_run(&self.api_key, &mut slack_handler) {
+                Ok(()) => warn!("Slack connection closed."),
+                Err(e) => error!("Slack client error: {}", e)
+            }
+
+            // Only back off further if the connection didn't stay up for a while
+            let stable = slack_handler.connected_at.map_or(false, |connected_at| connected_at.elapsed() >= Duration::from_secs(STABLE_CONNECTION_SECS));
+            reconnect_attempt = if stable { 0 } else { reconnect_attempt + 1 };
+
+            let backoff = cmp::min(RECONNECT_BACKOFF_BASE << cmp::min(reconnect_attempt, 6), RECONNECT_BACKOFF_MAX);
+            info!("Reconnecting to Slack in {} seconds ...", backoff);
+            thread::sleep(Duration::from_secs(backoff));
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::mpsc;
+use std::sync::mpsc::{SyncSender, Receiver};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use commands::CommandHandler;
+use templates::Templates;
+
+use serde_json::Value;
+
+pub struct SlackUser {
+    pub id: String,
+    pub name: String,
+    pub real_name: String,
+    pub is_bot: bool
+}
+
+pub trait SlackOutput {
+    fn send_message(&self, channel: &str, text: &str) -> Result<(), Box<Error>>;
+
+    // Transports that can't render Block Kit fall back to the plain text of the message
+    fn send_blocks(&self, channel: &str, text: &str, _blocks: &Value) -> Result<(), Box<Error>> {
+        self.send_message(channel, text)
+    }
+
+    // Posts to a channel other than the one being replied to, which some outputs can only reply to
+    fn post_message(&self, channel: &str, text: &str) -> Result<(), Box<Error>> {
+        self.send_message(channel, text)
+    }
+
+    // Returns the ID of the bot's DM channel with a user, opening one if there isn't one yet
+    fn open_dm(&self, user: &str) -> Result<String, Box<Error>>;
+
+    fn lookup_user(&self, user: &str) -> Result<Option<SlackUser>, Box<Error>>;
+}
+
+pub trait SlackTransport {
+    fn run(&mut self, command_handler: &CommandHandler, buffer: &mut NotificationBuffer);
+}
+
+pub struct IncomingMessage {
+    pub user: String,
+    pub channel: String,
+    pub text: String
+}
+
+// Returns the command contained in a message if it was meant for the bot: anything sent in a direct message,
+// or anything that starts by mentioning the bot, with the mention itself stripped off
+pub fn addressed_text(text: &str, is_direct: bool, bot_user_id: &str) -> Option<String> {
+    let mention = format!("<@{}>", bot_user_id);
+    let trimmed = text.trim();
+    if trimmed.starts_with(&mention[..]) {
+        let command = trimmed[mention.len()..].trim_start_matches(|c: char| c == ':' || c == ',' || c.is_whitespace());
+        return Some(command.to_string());
+    }
+    if is_direct {
+        return Some(trimmed.to_string());
+    }
+    None
+}
+
+#[derive(Clone)]
+pub struct NotificationCard {
+    pub id: String,
+    pub url: String,
+    pub writer: String,
+    // Reminders can be snoozed, while move notifications let the tracker untrack the writer instead
+    pub snooze: bool
+}
+
+#[derive(Clone)]
+pub struct Notification {
+    pub channel: String,
+    pub text: String,
+    // The recipient's language, which the buttons are labelled in
+    pub language: String,
+    pub card: Option<NotificationCard>
+}
+
+impl Notification {
+    pub fn blocks(&self, templates: &Templates) -> Option<Value> {
+        let templates = templates.in_language(&self.language);
+        self.card.as_ref().map(|card| {
+            let first_button = if card.snooze {
+                json!({
+                    "type": "button",
+                    "text": { "type": "plain_text", "text": templates.render("button.snooze", &[]) },
+                    "action_id": "snooze_card",
+                    "value": &card.id
+                })
+            }
+            else {
+                json!({
+                    "type": "button",
+                    "text": { "type": "plain_text", "text": templates.render("button.untrack", &[]) },
+                    "action_id": "untrack_writer",
+                    "value": &card.writer
+                })
+            };
+
+            json!([
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": &self.text }
+                },
+                {
+                    "type": "actions",
+                    "elements": [
+                        first_button,
+                        {
+                            "type": "button",
+                            "text": { "type": "plain_text", "text": templates.render("button.mute", &[]) },
+                            "action_id": "mute_card",
+                            "value": &card.id
+                        },
+                        {
+                            "type": "button",
+                            "text": { "type": "plain_text", "text": templates.render("button.open", &[]) },
+                            "action_id": "open_card",
+                            "url": &card.url
+                        }
+                    ]
+                }
+            ])
+        })
+    }
+}
+
+// How many notifications can wait for a flush before whoever sends the next one is held up until there is room
+pub const NOTIFICATION_BUFFER_SIZE: usize = 1000;
+
+// Anything that wants to notify Slack users goes through here rather than straight into the channel,
+// so that the webhook knows there is something to flush
+#[derive(Clone)]
+pub struct NotificationSender {
+    buffer_tx: SyncSender<Notification>,
+    buffer_count: Arc<AtomicUsize>,
+    blocking: bool
+}
+
+impl NotificationSender {
+    pub fn new(buffer_tx: SyncSender<Notification>, buffer_count: Arc<AtomicUsize>) -> NotificationSender {
+        NotificationSender {
+            buffer_tx: buffer_tx,
+            buffer_count: buffer_count,
+            blocking: true
+        }
+    }
+
+    // For senders on the thread that flushes the buffer, which would wait forever for room they can only make
+    // themselves. A full buffer is an error instead.
+    pub fn non_blocking(&self) -> NotificationSender {
+        NotificationSender {
+            buffer_tx: self.buffer_tx.clone(),
+            buffer_count: Arc::clone(&self.buffer_count),
+            blocking: false
+        }
+    }
+
+    pub fn send(&self, notification: Notification) -> Result<(), Box<Error>> {
+        if self.blocking {
+            self.buffer_tx.send(notification)?;
+        }
+        else {
+            self.buffer_tx.try_send(notification)?;
+        }
+
+        // Increment the buffer count to notify the webhook that a flush needs to happen
+        self.buffer_count.fetch_add(1, Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
+pub struct NotificationBuffer {
+    buffer_rx: Receiver<Notification>,
+    templates: Templates,
+    // Notifications that could not be delivered, kept across reconnects until the next flush
+    pending: VecDeque<Notification>
+}
+
+impl NotificationBuffer {
+    pub fn new(buffer_rx: Receiver<Notification>, templates: Templates) -> NotificationBuffer {
+        NotificationBuffer {
+            buffer_rx: buffer_rx,
+            templates: templates,
+            pending: VecDeque::new()
+        }
+    }
+
+    pub fn flush(&mut self, output: &SlackOutput) {
+        loop {
+            match self.buffer_rx.try_recv() {
+                Ok(notification) => self.pending.push_back(notification),
+                Err(mpsc::TryRecvError::Empty) => break,
+                // Every sender is gone, which only happens while shutting down. What is already here still goes out.
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    error!("Every notification sender has been dropped, {} messages left to send.", self.pending.len());
+                    break;
+                }
+            }
+        }
+
+        while let Some(notification) = self.pending.pop_front() {
+            let result = match notification.blocks(&self.templates) {
+                Some(blocks) => output.send_blocks(&notification.channel, &notification.text, &blocks),
+                None => output.send_message(&notification.channel, &notification.text)
+            };
+            if let Err(e) = result {
+                // Keep the notification (and everything after it) around until the connection comes back
+                warn!("Failed to send notification, {} messages left in buffer: {}", self.pending.len() + 1, e);
+                self.pending.push_front(notification);
+                break;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slack_fake::FakeSlack;
+
+    fn notification() -> Notification {
+        Notification {
+            channel: "D1".to_string(),
+            text: "Hello".to_string(),
+            language: "en".to_string(),
+            card: None
+        }
+    }
+
+    #[test]
+    fn non_blocking_senders_fail_when_the_buffer_is_full() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let count = Arc::new(AtomicUsize::new(0));
+        let sender = NotificationSender::new(tx, Arc::clone(&count)).non_blocking();
+
+        sender.send(notification()).unwrap();
+        assert!(sender.send(notification()).is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        rx.try_recv().unwrap();
+        sender.send(notification()).unwrap();
+    }
+
+    #[test]
+    fn flushing_after_every_sender_is_gone_still_delivers() {
+        let (tx, rx) = mpsc::sync_channel(NOTIFICATION_BUFFER_SIZE);
+        NotificationSender::new(tx, Arc::new(AtomicUsize::new(0))).send(notification()).unwrap();
+
+        let slack = FakeSlack::new();
+        let mut buffer = NotificationBuffer::new(rx, Templates::defaults());
+        buffer.flush(&slack);
+        assert_eq!(slack.take_messages().len(), 1);
+    }
+
+    #[test]
+    fn buttons_are_labelled_in_the_recipients_language() {
+        let mut notification = notification();
+        notification.language = "es".to_string();
+        notification.card = Some(NotificationCard {
+            id: "5a3000000000000000000001".to_string(),
+            url: "https://trello.com/c/CrD1sHrt".to_string(),
+            writer: String::new(),
+            snooze: true
+        });
+
+        let blocks = notification.blocks(&Templates::defaults()).unwrap();
+        let labels : Vec<&str> = blocks[1]["elements"].as_array().unwrap().iter()
+            .map(|button| button["text"]["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, vec!["Posponer una semana", "Silenciar esta tarjeta", "Abrir en Trello"]);
+    }
+}
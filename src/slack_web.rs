@@ -0,0 +1,162 @@
+use std::error::Error;
+
+use slack_transport::{SlackOutput, SlackUser};
+
+use reqwest::Client;
+use reqwest::header::{Authorization, Bearer};
+use serde_json::Value;
+
+const WEB_API_URL: &'static str = "https://slack.com/api";
+
+pub struct WebApiOutput {
+    api_key: String,
+    http_client: Client
+}
+
+impl WebApiOutput {
+    pub fn new(api_key: &str) -> WebApiOutput {
+        WebApiOutput {
+            api_key: api_key.to_string(),
+            http_client: Client::new()
+        }
+    }
+
+    pub fn call(&self, method: &str, body: &Value) -> Result<Value, Box<Error>> {
+        let mut resp = self.http_client
+            .post(&format!("{}/{}", WEB_API_URL, method))
+            .header(Authorization(Bearer { token: self.api_key.clone() }))
+            .json(body)
+            .send()?;
+        WebApiOutput::check(method, resp.json()?)
+    }
+
+    // Read methods such as users.info don't accept JSON bodies, only form fields
+    pub fn call_form(&self, method: &str, fields: &[(&str, &str)]) -> Result<Value, Box<Error>> {
+        let mut resp = self.http_client
+            .post(&format!("{}/{}", WEB_API_URL, method))
+            .header(Authorization(Bearer { token: self.api_key.clone() }))
+            .form(fields)
+            .send()?;
+        WebApiOutput::check(method, resp.json()?)
+    }
+
+    fn check(method: &str, result: Value) -> Result<Value, Box<Error>> {
+        // The Web API reports failures in the body, not in the status code
+        if result.get("ok").and_then(|ok| ok.as_bool()) != Some(true) {
+            let reason = result.get("error").and_then(|e| e.as_str()).unwrap_or("unknown error");
+            return Err(From::from(format!("Slack API method {} failed: {}", method, reason)));
+        }
+
+        Ok(result)
+    }
+
+    pub fn bot_user_id(&self) -> Result<String, Box<Error>> {
+        let result = self.call("auth.test", &json!({}))?;
+        let user_id = result.get("user_id").and_then(|id| id.as_str()).ok_or("Slack did not return the bot's user ID")?;
+        Ok(user_id.to_string())
+    }
+}
+
+impl SlackOutput for WebApiOutput {
+    fn send_message(&self, channel: &str, text: &str) -> Result<(), Box<Error>> {
+        self.call("chat.postMessage", &json!({
+            "channel": channel,
+            "text": text
+        }))?;
+        Ok(())
+    }
+
+    fn send_blocks(&self, channel: &str, text: &str, blocks: &Value) -> Result<(), Box<Error>> {
+        self.call("chat.postMessage", &json!({
+            "channel": channel,
+            "text": text,
+            "blocks": blocks
+        }))?;
+        Ok(())
+    }
+
+    fn open_dm(&self, user: &str) -> Result<String, Box<Error>> {
+        let result = self.call("conversations.open", &json!({
+            "users": user
+        }))?;
+        let channel = result.get("channel").and_then(|c| c.get("id")).and_then(|id| id.as_str()).ok_or("Slack did not return a DM channel")?;
+        Ok(channel.to_string())
+    }
+
+    fn lookup_user(&self, user: &str) -> Result<Option<SlackUser>, Box<Error>> {
+        let result = match self.call_form("users.info", &[("user", user)]) {
+            Ok(result) => result,
+            Err(ref e) if e.to_string().ends_with("user_not_found") => return Ok(None),
+            Err(e) => return Err(e)
+        };
+
+        let field = |name: &str| result["user"][name].as_str().unwrap_or("").to_string();
+        Ok(Some(SlackUser {
+            id: field("id"),
+            name: field("name"),
+            real_name: result["user"]["profile"]["real_name"].as_str().unwrap_or("").to_string(),
+            is_bot: result["user"]["is_bot"].as_bool().unwrap_or(false)
+        }))
+    }
+}
+
+// Replies to slash commands and button clicks go back through the response URL that Slack hands us,
+// which works even in channels the bot hasn't been invited to. Anything else still goes through the Web API.
+pub struct ResponseUrlOutput<'a> {
+    response_url: String,
+    http_client: Client,
+    web: &'a WebApiOutput
+}
+
+impl<'a> ResponseUrlOutput<'a> {
+    pub fn new(response_url: &str, web: &'a WebApiOutput) -> ResponseUrlOutput<'a> {
+        ResponseUrlOutput {
+            response_url: response_url.to_string(),
+            http_client: Client::new(),
+            web: web
+        }
+    }
+
+    fn post(&self, body: &Value) -> Result<(), Box<Error>> {
+        let resp = self.http_client
+            .post(&self.response_url)
+            .json(body)
+            .send()?;
+        if !resp.status().is_success() {
+            return Err(From::from(format!("Slack response URL returned status {}", resp.status())));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SlackOutput for ResponseUrlOutput<'a> {
+    fn send_message(&self, _channel: &str, text: &str) -> Result<(), Box<Error>> {
+        self.post(&json!({
+            "response_type": "ephemeral",
+            "replace_original": false,
+            "text": text
+        }))
+    }
+
+    fn send_blocks(&self, _channel: &str, text: &str, blocks: &Value) -> Result<(), Box<Error>> {
+        self.post(&json!({
+            "response_type": "ephemeral",
+            "replace_original": false,
+            "text": text,
+            "blocks": blocks
+        }))
+    }
+
+    // Replies only ever reach whoever used the command, so anything meant for someone else goes through the Web API
+    fn post_message(&self, channel: &str, text: &str) -> Result<(), Box<Error>> {
+        self.web.send_message(channel, text)
+    }
+
+    fn open_dm(&self, user: &str) -> Result<String, Box<Error>> {
+        self.web.open_dm(user)
+    }
+
+    fn lookup_user(&self, user: &str) -> Result<Option<SlackUser>, Box<Error>> {
+        self.web.lookup_user(user)
+    }
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+
+use bson::Document;
+use mongodb::coll::options::{FindOptions, UpdateOptions};
+use mongodb::db::{Database, ThreadedDatabase};
+
+// The handful of collection operations articlebot needs, so MongoDB can be swapped for an in-memory store in tests
+pub trait Store : Send + Sync {
+    fn find(&self, collection: &str, filter: Document, sort: Option<Document>) -> Result<Vec<Document>, Box<Error>>;
+    fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>, Box<Error>>;
+    fn find_one_and_delete(&self, collection: &str, filter: Document) -> Result<Option<Document>, Box<Error>>;
+    fn insert_one(&self, collection: &str, document: Document) -> Result<(), Box<Error>>;
+    // Returns the number of documents that matched the filter
+    fn update_one(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> Result<i32, Box<Error>>;
+    // Returns the number of documents that were deleted
+    fn delete_one(&self, collection: &str, filter: Document) -> Result<i32, Box<Error>>;
+    fn delete_many(&self, collection: &str, filter: Document) -> Result<i32, Box<Error>>;
+}
+
+pub struct MongoStore {
+    db: Database
+}
+
+impl MongoStore {
+    pub fn new(db: Database) -> MongoStore {
+        MongoStore {
+            db: db
+        }
+    }
+}
+
+impl Store for MongoStore {
+    fn find(&self, collection: &str, filter: Document, sort: Option<Document>) -> Result<Vec<Document>, Box<Error>> {
+        let mut options = FindOptions::new();
+        options.sort = sort;
+
+        let mut documents = Vec::new();
+        for document in self.db.collection(collection).find(Some(filter), Some(options))? {
+            documents.push(document?);
+        }
+        Ok(documents)
+    }
+
+    fn find_one(&self, collection: &str, filter: Document) -> Result<Option<Document>, Box<Error>> {
+        Ok(self.db.collection(collection).find_one(Some(filter), None)?)
+    }
+
+    fn find_one_and_delete(&self, collection: &str, filter: Document) -> Result<Option<Document>, Box<Error>> {
+        Ok(self.db.collection(collection).find_one_and_delete(filter, None)?)
+    }
+
+    fn insert_one(&self, collection: &str, document: Document) -> Result<(), Box<Error>> {
+        self.db.collection(collection).insert_one(document, None)?;
+        Ok(())
+    }
+
+    fn update_one(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> Result<i32, Box<Error>> {
+        let mut options = UpdateOptions::new();
+        options.upsert = Some(upsert);
+
+        let result = self.db.collection(collection).update_one(filter, update, Some(options))?;
+        Ok(result.matched_count)
+    }
+
+    fn delete_one(&self, collection: &str, filter: Document) -> Result<i32, Box<Error>> {
+        Ok(self.db.collection(collection).delete_one(filter, None)?.deleted_count)
+    }
+
+    fn delete_many(&self, collection: &str, filter: Document) -> Result<i32, Box<Error>> {
+        Ok(self.db.collection(collection).delete_many(filter, None)?.deleted_count)
+    }
+}
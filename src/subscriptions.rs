@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use store::Store;
+
+use bson::Document;
+
+const SUBSCRIPTIONS_COLLECTION: &'static str = "subscriptions";
+
+// Someone who wants to hear about a Trello user's cards somewhere other than their own Slack DM,
+// e.g. a freelancer's email address or a team's Discord channel
+pub struct Subscription {
+    pub trello: String,
+    pub sink: String,
+    pub target: String,
+    // The Slack user who set the subscription up
+    pub creator: String
+}
+
+impl Subscription {
+    fn from_document(sdoc: &Document) -> Subscription {
+        let string = |key: &str| sdoc.get_str(key).unwrap_or("").to_string();
+        Subscription {
+            trello: string("trello"),
+            sink: string("sink"),
+            target: string("target"),
+            creator: string("creator")
+        }
+    }
+
+    // How the subscription shows up in an event's recipients, next to the Slack users who were notified
+    pub fn recipient(&self) -> String {
+        format!("{}:{}", self.sink, self.target)
+    }
+}
+
+pub struct Subscriptions {
+    store: Arc<Store>
+}
+
+impl Subscriptions {
+    pub fn new(store: Arc<Store>) -> Subscriptions {
+        Subscriptions {
+            store: store
+        }
+    }
+
+    // Returns false if the subscription already existed
+    pub fn add(&self, subscription: &Subscription) -> Result<bool, Box<Error>> {
+        let lookup = doc! {
+            "trello": &subscription.trello,
+            "sink": &subscription.sink,
+            "target": &subscription.target
+        };
+        if self.store.find_one(SUBSCRIPTIONS_COLLECTION, lookup.clone())?.is_some() {
+            return Ok(false);
+        }
+
+        self.store.insert_one(SUBSCRIPTIONS_COLLECTION, doc! {
+            "trello": &subscription.trello,
+            "sink": &subscription.sink,
+            "target": &subscription.target,
+            "creator": &subscription.creator
+        })?;
+        Ok(true)
+    }
+
+    // Removes every subscription to a Trello user's cards at the given target, returning how many there were.
+    // Given a creator, only the subscriptions they set up themselves are removed.
+    pub fn remove(&self, trello: &str, target: &str, creator: Option<&str>) -> Result<i32, Box<Error>> {
+        let mut filter = doc! {
+            "trello": trello,
+            "target": target
+        };
+        if let Some(creator) = creator {
+            filter.insert("creator", creator);
+        }
+        self.store.delete_many(SUBSCRIPTIONS_COLLECTION, filter)
+    }
+
+    pub fn for_member(&self, trello: &str) -> Result<Vec<Subscription>, Box<Error>> {
+        let sdocs = self.store.find(SUBSCRIPTIONS_COLLECTION, doc! {
+            "trello": trello
+        }, None)?;
+        Ok(sdocs.iter().map(Subscription::from_document).collect())
+    }
+
+    pub fn all(&self) -> Result<Vec<Subscription>, Box<Error>> {
+        let sdocs = self.store.find(SUBSCRIPTIONS_COLLECTION, doc! {}, Some(doc! { "trello": 1 }))?;
+        Ok(sdocs.iter().map(Subscription::from_document).collect())
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, Arc};
+use std::time::{Duration, Instant};
+use std::{cmp, process, thread};
+
+use chrono::prelude::*;
+
+// A worker that crashes more than MAX_CRASHES times within CRASH_WINDOW takes the whole process down with it,
+// so that keepalive.sh can bring the bot back from a clean slate
+const MAX_CRASHES: usize = 5;
+const CRASH_WINDOW: u64 = 600;
+const RESTART_BACKOFF_BASE: u64 = 1;
+const RESTART_BACKOFF_MAX: u64 = 60;
+const CRASH_EXIT_CODE: i32 = 1;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum WorkerState {
+    Running,
+    Restarting,
+    Failed
+}
+
+#[derive(Clone)]
+pub struct WorkerStatus {
+    pub name: String,
+    pub state: WorkerState,
+    pub restarts: u32,
+    pub started: DateTime<Utc>,
+    pub last_error: Option<String>
+}
+
+#[derive(Clone)]
+pub struct SupervisorHandle {
+    statuses: Arc<Mutex<Vec<WorkerStatus>>>
+}
+
+impl SupervisorHandle {
+    pub fn statuses(&self) -> Vec<WorkerStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    fn update<F : FnOnce(&mut WorkerStatus)>(&self, name: &str, f: F) {
+        let mut statuses = self.statuses.lock().unwrap();
+        if let Some(status) = statuses.iter_mut().find(|status| status.name == name) {
+            f(status);
+        }
+    }
+}
+
+pub struct Supervisor {
+    handle: SupervisorHandle
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            handle: SupervisorHandle {
+                statuses: Arc::new(Mutex::new(Vec::new()))
+            }
+        }
+    }
+
+    pub fn handle(&self) -> SupervisorHandle {
+        self.handle.clone()
+    }
+
+    pub fn spawn<F>(&self, name: &str, mut worker: F) where F : FnMut() + Send + 'static {
+        self.handle.statuses.lock().unwrap().push(WorkerStatus {
+            name: name.to_string(),
+            state: WorkerState::Running,
+            restarts: 0,
+            started: Utc::now(),
+            last_error: None
+        });
+
+        let handle = self.handle.clone();
+        let name = name.to_string();
+        thread::Builder::new().name(name.clone()).spawn(move || {
+            let mut crashes : VecDeque<Instant> = VecDeque::new();
+            let window = Duration::from_secs(CRASH_WINDOW);
+
+            loop {
+                info!("Starting worker \"{}\" ...", name);
+                handle.update(&name, |status| {
+                    status.state = WorkerState::Running;
+                    status.started = Utc::now();
+                });
+
+                // Workers are meant to run forever, so returning is treated the same as panicking
+                let reason = match panic::catch_unwind(AssertUnwindSafe(|| worker())) {
+                    Ok(()) => "worker returned".to_string(),
+                    Err(payload) => {
+                        if let Some(message) = payload.downcast_ref::<&str>() {
+                            message.to_string()
+                        }
+                        else if let Some(message) = payload.downcast_ref::<String>() {
+                            message.clone()
+                        }
+                        else {
+                            "worker panicked".to_string()
+                        }
+                    }
+                };
+
+                error!("Worker \"{}\" stopped: {}", name, reason);
+
+                let now = Instant::now();
+                crashes.push_back(now);
+                while crashes.front().map_or(false, |crash| now.duration_since(*crash) > window) {
+                    crashes.pop_front();
+                }
+
+                if crashes.len() > MAX_CRASHES {
+                    handle.update(&name, |status| {
+                        status.state = WorkerState::Failed;
+                        status.last_error = Some(reason.clone());
+                    });
+                    error!("Worker \"{}\" crashed {} times in {} seconds, exiting.", name, crashes.len(), CRASH_WINDOW);
+                    process::exit(CRASH_EXIT_CODE);
+                }
+
+                handle.update(&name, |status| {
+                    status.state = WorkerState::Restarting;
+                    status.restarts += 1;
+                    status.last_error = Some(reason.clone());
+                });
+
+                let backoff = cmp::min(RESTART_BACKOFF_BASE << (crashes.len() - 1), RESTART_BACKOFF_MAX);
+                warn!("Restarting worker \"{}\" in {} seconds ...", name, backoff);
+                thread::sleep(Duration::from_secs(backoff));
+            }
+        }).expect("Supervisor spawn error");
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+use store::Store;
+
+use chrono::prelude::*;
+use serde_json;
+
+pub const DEFAULT_LANGUAGE: &'static str = "en";
+const LANGUAGES_COLLECTION: &'static str = "languages";
+// English is the reference every other language is checked against, and what they fall back to
+const LOCALES: &'static [(&'static str, &'static str)] = &[
+    ("en", include_str!("../templates/en.json")),
+    ("es", include_str!("../templates/es.json"))
+];
+// Notifications are rendered with everything known about the card, whether or not the default text uses it
+const EXTRA_VARIABLES: &'static [(&'static str, &'static [&'static str])] = &[
+    ("notify.moved", &["url", "board", "writer", "actor"]),
+    ("notify.stale", &["url", "board"]),
+    ("notify.due_day", &["url", "board"]),
+    ("notify.due_hour", &["url", "board"]),
+    ("notify.overdue", &["url", "board"]),
+    // Dates are rendered with every part of them, so shorter forms can still add the year
+    ("date.day", &["year"]),
+    ("date.day_time", &["year"]),
+    ("date.time", &["month", "day", "year"])
+];
+
+enum Part {
+    Text(String),
+    Variable(String)
+}
+
+// A message with {{variable}} placeholders in it
+struct Template {
+    parts: Vec<Part>
+}
+
+impl Template {
+    fn parse(text: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..].find("}}").ok_or(format!("\"{}\" has a {{{{ without a matching }}}}", text))? + start;
+            let variable = rest[start + 2..end].trim();
+            if variable.is_empty() || !variable.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(format!("\"{}\" has an invalid variable {{{{{}}}}}", text, variable));
+            }
+            parts.push(Part::Variable(variable.to_string()));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template {
+            parts: parts
+        })
+    }
+
+    fn variables(&self) -> Vec<&str> {
+        self.parts.iter().filter_map(|part| match *part {
+            Part::Variable(ref name) => Some(&name[..]),
+            Part::Text(_) => None
+        }).collect()
+    }
+
+    fn render(&self, variables: &[(&str, &str)]) -> String {
+        let mut text = String::new();
+        for part in self.parts.iter() {
+            match *part {
+                Part::Text(ref value) => text.push_str(value),
+                Part::Variable(ref name) => match variables.iter().find(|&&(key, _)| key == name) {
+                    Some(&(_, value)) => text.push_str(value),
+                    None => warn!("No value was given for {{{{{}}}}}", name)
+                }
+            }
+        }
+        text
+    }
+}
+
+type Catalog = HashMap<String, Template>;
+
+fn parse_catalog(texts: HashMap<String, String>) -> Result<Catalog, String> {
+    let mut catalog = HashMap::new();
+    for (name, text) in texts {
+        let template = Template::parse(&text).map_err(|e| format!("Template {}: {}", name, e))?;
+        catalog.insert(name, template);
+    }
+    Ok(catalog)
+}
+
+// Translations and overrides can only replace templates articlebot knows about, and only use the variables it fills in for them
+fn check_override(name: &str, template: &Template, defaults: &Catalog) -> Result<(), String> {
+    let default = defaults.get(name).ok_or(format!("There is no template called {}", name))?;
+    let mut allowed = default.variables();
+    if let Some(&(_, extra)) = EXTRA_VARIABLES.iter().find(|&&(template, _)| template == name) {
+        allowed.extend(extra.iter());
+    }
+
+    for variable in template.variables() {
+        if !allowed.contains(&variable) {
+            allowed.sort();
+            allowed.dedup();
+            return Err(format!("Template {} uses {{{{{}}}}}, but only has {}", name, variable,
+                allowed.iter().map(|allowed| format!("{{{{{}}}}}", allowed)).collect::<Vec<String>>().join(", ")));
+        }
+    }
+    Ok(())
+}
+
+// Templates by language, each of which only needs to translate some of the English ones
+type Catalogs = HashMap<String, Catalog>;
+
+fn parse_locales(json: &str) -> Result<HashMap<String, HashMap<String, String>>, String> {
+    serde_json::from_str(json).map_err(|e| format!("Templates are not a JSON object of languages, each with an object of strings: {}", e))
+}
+
+// Adds translations or overrides to a language, checking them against the English templates first
+fn merge_catalog(catalogs: &mut Catalogs, language: &str, catalog: Catalog) -> Result<(), String> {
+    if let Some(english) = catalogs.get(DEFAULT_LANGUAGE) {
+        for (name, template) in catalog.iter() {
+            check_override(name, template, english).map_err(|e| format!("In {}: {}", language, e))?;
+        }
+    }
+    catalogs.entry(language.to_string()).or_insert(HashMap::new()).extend(catalog);
+    Ok(())
+}
+
+fn load_catalogs(path: Option<&str>) -> Result<Catalogs, String> {
+    let mut catalogs = HashMap::new();
+    for &(language, json) in LOCALES.iter() {
+        let texts = serde_json::from_str(json).expect("The bundled templates are not a JSON object of strings");
+        let catalog = parse_catalog(texts).expect("The bundled templates are invalid");
+        merge_catalog(&mut catalogs, language, catalog).expect("The bundled templates are invalid");
+    }
+
+    if let Some(path) = path {
+        let mut json = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut json)).map_err(|e| format!("Could not read templates from {}: {}", path, e))?;
+
+        // Overrides can also add a language that isn't bundled with articlebot
+        for (language, texts) in parse_locales(&json)? {
+            let overrides = parse_catalog(texts).map_err(|e| format!("In {}: {}", language, e))?;
+            merge_catalog(&mut catalogs, &language, overrides)?;
+        }
+    }
+    Ok(catalogs)
+}
+
+// Every message articlebot sends, looked up by name and language. Each one can be overridden from a JSON file,
+// which is checked when it is loaded so that a bad template is caught at startup rather than mid-conversation.
+#[derive(Clone)]
+pub struct Templates {
+    catalogs: Arc<RwLock<Catalogs>>,
+    path: Option<String>,
+    language: String
+}
+
+impl Templates {
+    pub fn load(path: Option<&str>) -> Result<Templates, String> {
+        Ok(Templates {
+            catalogs: Arc::new(RwLock::new(load_catalogs(path)?)),
+            path: path.map(|path| path.to_string()),
+            language: DEFAULT_LANGUAGE.to_string()
+        })
+    }
+
+    pub fn defaults() -> Templates {
+        Templates::load(None).unwrap()
+    }
+
+    // The same templates, rendered in another language where they have been translated
+    pub fn in_language(&self, language: &str) -> Templates {
+        Templates {
+            catalogs: Arc::clone(&self.catalogs),
+            path: self.path.clone(),
+            language: language.to_string()
+        }
+    }
+
+    pub fn languages(&self) -> Vec<String> {
+        let mut languages : Vec<String> = self.catalogs.read().unwrap().keys().cloned().collect();
+        languages.sort();
+        languages
+    }
+
+    // Reads the overrides again, keeping the current templates if they no longer check out
+    pub fn reload(&self) -> Result<(), String> {
+        let catalogs = load_catalogs(self.path.as_ref().map(|path| &path[..]))?;
+        *self.catalogs.write().unwrap() = catalogs;
+        Ok(())
+    }
+
+    pub fn render(&self, name: &str, variables: &[(&str, &str)]) -> String {
+        let catalogs = self.catalogs.read().unwrap();
+        let translated = catalogs.get(&self.language).and_then(|catalog| catalog.get(name));
+        match translated.or_else(|| catalogs[DEFAULT_LANGUAGE].get(name)) {
+            Some(template) => template.render(variables),
+            None => {
+                error!("There is no template called {}", name);
+                name.to_string()
+            }
+        }
+    }
+
+    // Dates are templates too, since month names and the order a date is written in depend on the language
+    pub fn format_date(&self, name: &str, date: &DateTime<Utc>) -> String {
+        let month = self.render(&format!("month.{}", date.month()), &[]);
+        self.render(name, &[("month", &month), ("day", &date.day().to_string()), ("year", &date.year().to_string()),
+            ("time", &date.format("%H:%M").to_string())])
+    }
+}
+
+// Which language each Slack user wants articlebot to talk to them in
+pub struct LanguagePreferences {
+    store: Arc<Store>
+}
+
+impl LanguagePreferences {
+    pub fn new(store: Arc<Store>) -> LanguagePreferences {
+        LanguagePreferences {
+            store: store
+        }
+    }
+
+    pub fn set(&self, uid: &str, language: &str) -> Result<(), Box<Error>> {
+        self.store.update_one(LANGUAGES_COLLECTION, doc! {
+            "uid": uid
+        }, doc! {
+            "$set": { "language": language }
+        }, true)?;
+        Ok(())
+    }
+
+    pub fn get(&self, uid: &str) -> Result<String, Box<Error>> {
+        let ldoc = self.store.find_one(LANGUAGES_COLLECTION, doc! {
+            "uid": uid
+        })?;
+        Ok(ldoc.and_then(|ldoc| ldoc.get_str("language").ok().map(|language| language.to_string())).unwrap_or(DEFAULT_LANGUAGE.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use memory_store::MemoryStore;
+
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    fn check(json: &str) -> Result<(), String> {
+        let defaults = parse_catalog(serde_json::from_str(LOCALES[0].1).unwrap()).unwrap();
+        let texts = serde_json::from_str(json).map_err(|e| format!("{}", e))?;
+        for (name, template) in parse_catalog(texts)?.iter() {
+            check_override(name, template, &defaults)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn variables_are_filled_in() {
+        let templates = Templates::defaults();
+        assert_eq!(templates.render("notify.moved", &[("card", "Library hours extended"), ("from", "Drafting"), ("to", "Copy Editing")]),
+            "Your card _Library hours extended_ has been moved from *Drafting* to *Copy Editing*.");
+        assert_eq!(Template::parse("{{ a }} and {{b}}!").unwrap().render(&[("a", "1"), ("b", "2")]), "1 and 2!");
+    }
+
+    #[test]
+    fn dates_are_written_in_each_language() {
+        let templates = Templates::defaults();
+        let date = Utc.ymd(2018, 6, 1).and_hms(12, 15, 0);
+        assert_eq!(templates.format_date("date.full", &date), "Jun 1, 2018 12:15 UTC");
+        assert_eq!(templates.format_date("date.day", &date), "Jun 1");
+        assert_eq!(templates.in_language("es").format_date("date.full", &date), "1 de jun de 2018, 12:15 UTC");
+        assert_eq!(templates.in_language("es").format_date("date.day_time", &date), "1 de jun a las 12:15");
+    }
+
+    #[test]
+    fn overrides_are_checked_against_the_defaults() {
+        assert!(check(r#"{ "notify.moved": "{{writer}}: {{card}} is now in {{to}} on {{board}} ({{url}})" }"#).is_ok());
+        assert_eq!(check(r#"{ "notify.moved": "{{card}} is due {{due}}" }"#).unwrap_err(),
+            "Template notify.moved uses {{due}}, but only has {{actor}}, {{board}}, {{card}}, {{from}}, {{to}}, {{url}}, {{writer}}");
+        assert_eq!(check(r#"{ "link.done": "Linked to {{card}}" }"#).unwrap_err(), "Template link.done uses {{card}}, but only has {{username}}");
+        assert_eq!(check(r#"{ "no.such.template": "Hi" }"#).unwrap_err(), "There is no template called no.such.template");
+        assert!(check(r#"{ "hello": "Hi {{" }"#).unwrap_err().starts_with("Template hello: "));
+        assert!(check(r#"[ "hello" ]"#).is_err());
+    }
+
+    #[test]
+    fn translations_fall_back_to_english() {
+        let path = env::temp_dir().join("articlebot-locale-templates.json");
+        File::create(&path).unwrap().write_all(br#"{ "fr": { "hello": "Bonjour." } }"#).unwrap();
+        let templates = Templates::load(path.to_str()).unwrap();
+
+        assert_eq!(templates.languages(), vec!["en", "es", "fr"]);
+        assert_eq!(templates.in_language("es").render("hello", &[]), "Hola.");
+        assert_eq!(templates.in_language("fr").render("hello", &[]), "Bonjour.");
+        assert_eq!(templates.in_language("fr").render("version", &[("version", "1.0")]), "Running v1.0.");
+        assert_eq!(templates.in_language("xx").render("hello", &[]), "Hello there.");
+    }
+
+    #[test]
+    fn translations_are_checked_against_english() {
+        let path = env::temp_dir().join("articlebot-bad-locale-templates.json");
+        File::create(&path).unwrap().write_all(br#"{ "es": { "link.done": "Vinculado a {{card}}" } }"#).unwrap();
+        assert_eq!(Templates::load(path.to_str()).err().unwrap(), "In es: Template link.done uses {{card}}, but only has {{username}}");
+    }
+
+    #[test]
+    fn languages_default_to_english() {
+        let preferences = LanguagePreferences::new(Arc::new(MemoryStore::new()));
+        assert_eq!(preferences.get("U1").unwrap(), "en");
+        preferences.set("U1", "es").unwrap();
+        assert_eq!(preferences.get("U1").unwrap(), "es");
+    }
+}
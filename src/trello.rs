@@ -1,125 +1,466 @@
 use std::time::Duration;
 use std::{env, thread};
 use std::error::Error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
+use cache::TtlCache;
+use trello_client::{TrelloApi, TrelloError};
 use trello_listeners::ActionListener;
 
-use reqwest::Client;
-use reqwest::header::UserAgent;
 use chrono::prelude::*;
+use chrono::SecondsFormat;
 use trello_models::*;
 use serde_json::{Value, from_value};
+use url::form_urlencoded::byte_serialize;
 
-const API_URL: &'static str = "https://api.trello.com/1";
-const USER_AGENT: &'static str = "Mozilla/5.0 (Windows NT 5.1; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/46.0.2486.0 Safari/537.36 Edge/13.10586";
 const UPDATE_INTERVAL: u64 = 30;
+const BATCH_LIMIT: usize = 10;
+// Trello only returns 50 actions unless asked for more, and 1000 is as many as it allows
+const ACTIONS_LIMIT: usize = 1000;
+const MAX_ACTION_ATTEMPTS: u32 = 5;
+const CARD_CACHE_TTL: u64 = 120;
+const MEMBER_CACHE_TTL: u64 = 3600;
+const LIST_CACHE_TTL: u64 = 3600;
+const BOARD_FIELDS: &'static str = "id,name,desc,closed,url,shortLink";
+const CARD_FIELDS: &'static str = "id,name,desc,idBoard,idList,idMembers,idLabels,labels,shortLink,url,closed,pos,due,dueComplete,dateLastActivity";
+const LIST_FIELDS: &'static str = "id,name,idBoard,closed,pos";
+const LABEL_FIELDS: &'static str = "id,idBoard,name,color";
+const HISTORY_ACTIONS: &'static str = "createCard,copyCard,updateCard:idList";
+const MEMBER_FIELDS: &'static str = "id,fullName,initials,username";
 
-pub struct BoardHandler<L> {
+pub struct BoardHandler {
     pub id: String,
-    action_listener: L,
-    http_url: String,
-    http_since_parameter: DateTime<Utc>,
-    http_token_parameters: String,
-    http_client: Client
+    state: Arc<Mutex<PollState>>,
+    client: Arc<TrelloApi>,
+    listeners: Vec<Box<ActionListener>>
 }
 
-impl<L : ActionListener> BoardHandler<L> {
-    pub fn new(board_id: &str, trello_api_key: &str, trello_oauth_token: &str, action_listener : L) -> BoardHandler<L> {
+// Where polling a board is up to, kept apart from the board handler so that a restarted poller carries on from there
+pub struct PollState {
+    since: DateTime<Utc>,
+    failed: Vec<FailedAction>
+}
+
+impl PollState {
+    pub fn new() -> Arc<Mutex<PollState>> {
+        Arc::new(Mutex::new(PollState {
+            since: Utc::now(),
+            failed: Vec::new()
+        }))
+    }
+}
+
+// An action one of the listeners (by its index) couldn't handle, to be handed to it again on the next poll
+#[derive(Clone)]
+struct FailedAction {
+    listener: usize,
+    action: Action,
+    attempts: u32
+}
+
+// Filters look like "updateCard,commentCard", where each type can be narrowed down further, e.g. updateCard:idList
+fn filter_types(filter: &str) -> Vec<&str> {
+    filter.split(',').map(|action_type| action_type.trim()).filter(|action_type| !action_type.is_empty()).collect()
+}
+
+// Dates go into queries as ISO 8601 with a Z, since the + of an offset would have to be encoded
+fn query_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn wants_action(listener: &ActionListener, action: &Action) -> bool {
+    filter_types(listener.get_filtered_actions()).iter().any(|action_type| action_type.split(':').next() == Some(&action.action_type[..]))
+}
+
+impl BoardHandler {
+    pub fn new(board_id: &str, client: Arc<TrelloApi>) -> BoardHandler {
+        BoardHandler::with_state(board_id, client, PollState::new())
+    }
+
+    pub fn with_state(board_id: &str, client: Arc<TrelloApi>, state: Arc<Mutex<PollState>>) -> BoardHandler {
         BoardHandler {
             id: board_id.to_string(),
-            action_listener: action_listener,
-            http_since_parameter: Utc::now(),
-            http_url: format!("{}/boards/{}", API_URL, board_id).to_string(),
-            http_token_parameters: format!("key={}&token={}", trello_api_key, trello_oauth_token).to_string(),
-            http_client: Client::new()
+            state: state,
+            client: client,
+            listeners: Vec::new()
+        }
+    }
+
+    // Listeners are handed each action in the order they were added
+    pub fn add_listener(&mut self, listener: Box<ActionListener>) {
+        self.listeners.push(listener);
+    }
+
+    // Every action type that at least one listener is interested in, without repeats
+    fn get_filtered_actions(&self) -> String {
+        let mut action_types : Vec<&str> = Vec::new();
+        for listener in self.listeners.iter() {
+            for action_type in filter_types(listener.get_filtered_actions()) {
+                if !action_types.contains(&action_type) {
+                    action_types.push(action_type);
+                }
+            }
+        }
+        action_types.join(",")
+    }
+
+    fn handle(&self, listener: usize, action: &Action, attempts: u32, failed: &mut Vec<FailedAction>) {
+        let attempts = attempts + 1;
+        if let Err(e) = self.listeners[listener].on_action(action) {
+            let name = self.listeners[listener].name().to_string();
+            if attempts < MAX_ACTION_ATTEMPTS {
+                warn!("Listener {} failed to handle action {} (attempt {} of {}), retrying on the next poll: {}", name, action.id, attempts, MAX_ACTION_ATTEMPTS, e);
+                failed.push(FailedAction {
+                    listener: listener,
+                    action: action.clone(),
+                    attempts: attempts
+                });
+            }
+            else {
+                error!("Listener {} failed to handle action {} {} times, giving up: {}", name, action.id, attempts, e);
+            }
         }
     }
 
-    pub fn listen(&mut self) -> Result<(), Box<Error>> {
+    pub fn listen(&mut self) {
         info!("v{} listening for updates.", env::var("CARGO_PKG_VERSION").unwrap());
         loop {
-            let url = format!("{}/actions?filter={}&since={}&{}",
-                self.http_url, self.action_listener.get_filtered_actions(), self.http_since_parameter, self.http_token_parameters);
+            // A failed poll leaves the since parameter untouched, so the missed actions are picked up next time
+            if let Err(e) = self.poll() {
+                error!("Failed to poll board {}: {}", self.id, e);
+            }
+
+            thread::sleep(Duration::from_secs(UPDATE_INTERVAL));
+        }
+    }
+
+    pub fn poll(&mut self) -> Result<(), Box<Error>> {
+        let filter = self.get_filtered_actions();
+        if filter.is_empty() {
+            return Ok(());
+        }
 
-            let mut resp = self.http_client
-                .get(&url)
-                .header(UserAgent::new(USER_AGENT.to_string()))
-                .send()?;
+        // The state is only written back once the poll is through, so a poller that dies halfway starts it over
+        let (since, retries) = {
+            let state = self.state.lock().unwrap();
+            (state.since, state.failed.clone())
+        };
 
-            let actions : Vec<Action> = resp.json()?;
+        let poll_started = Utc::now();
+        let route = format!("/boards/{}/actions?filter={}&since={}", self.id, filter, query_date(&since));
 
-            info!("Found {} actions since last update.", actions.iter().count());
+        let actions : Vec<Action> = self.client.get(&route)?;
 
-            for action in actions.iter().rev() {
-                self.action_listener.on_action(action)?;
+        info!("Found {} actions since last update.", actions.iter().count());
+
+        // Each listener only sees the actions it asked for, and one listener failing doesn't keep the others from running
+        for listener in self.listeners.iter() {
+            let wanted : Vec<Action> = actions.iter().filter(|action| wants_action(&**listener, action)).cloned().collect();
+            if let Err(e) = listener.prefetch(&wanted) {
+                warn!("Listener {} failed to prefetch cards: {}", listener.name(), e);
             }
+        }
 
-            self.http_since_parameter = Utc::now();
+        // Since moves past every action regardless, so whatever a listener failed on is retried by that listener alone,
+        // before anything newer reaches it
+        let mut failed = Vec::new();
+        for retry in retries {
+            self.handle(retry.listener, &retry.action, retry.attempts, &mut failed);
+        }
 
-            thread::sleep(Duration::from_secs(UPDATE_INTERVAL));
+        for action in actions.iter().rev() {
+            for listener in 0..self.listeners.len() {
+                if wants_action(&*self.listeners[listener], action) {
+                    self.handle(listener, action, 0, &mut failed);
+                }
+            }
         }
+
+        let mut state = self.state.lock().unwrap();
+        state.since = poll_started;
+        state.failed = failed;
+
+        Ok(())
+    }
+
+    // Comments made anywhere on the board since the given time, newest first
+    pub fn get_comments(&self, since: DateTime<Utc>) -> Result<Vec<Action>, Box<Error>> {
+        let actions : Vec<Action> = self.client.get(&format!("/boards/{}/actions?filter=commentCard&since={}&limit={}",
+            self.id, query_date(&since), ACTIONS_LIMIT))?;
+        Ok(actions.into_iter().filter(|action| action.date >= since).collect())
+    }
+
+    pub fn get_board(&self) -> Result<Board, Box<Error>> {
+        let board : Board = self.client.get(&format!("/boards/{}?fields={}&lists=open&list_fields={}&labels=all&label_fields={}",
+            self.id, BOARD_FIELDS, LIST_FIELDS, LABEL_FIELDS))?;
+        Ok(board)
+    }
+
+    pub fn get_lists(&self) -> Result<Vec<List>, Box<Error>> {
+        let lists : Vec<List> = self.client.get(&format!("/boards/{}/lists?fields={}&filter=open", self.id, LIST_FIELDS))?;
+        Ok(lists)
+    }
+
+    pub fn get_labels(&self) -> Result<Vec<Label>, Box<Error>> {
+        let labels : Vec<Label> = self.client.get(&format!("/boards/{}/labels?fields={}", self.id, LABEL_FIELDS))?;
+        Ok(labels)
+    }
+
+    pub fn find_list(&self, name: &str) -> Result<Option<List>, Box<Error>> {
+        let name = name.to_lowercase();
+        Ok(self.get_lists()?.into_iter().find(|list| list.name.to_lowercase() == name))
+    }
+
+    pub fn get_memberships(&self) -> Result<Vec<Membership>, Box<Error>> {
+        let memberships : Vec<Membership> = self.client.get(&format!("/boards/{}/memberships?member=true&member_fields={}", self.id, MEMBER_FIELDS))?;
+        Ok(memberships)
+    }
+
+    pub fn get_cards(&self) -> Result<Vec<Card>, Box<Error>> {
+        let cards : Vec<Card> = self.client.get(&format!("/boards/{}/cards?filter=open&fields={}", self.id, CARD_FIELDS))?;
+        Ok(cards)
+    }
+
+    pub fn get_member_cards(&self, member_id: &str) -> Result<Vec<Card>, Box<Error>> {
+        Ok(self.get_cards()?.into_iter().filter(|card| card.id_members.iter().any(|id| id == member_id)).collect())
+    }
+
+    pub fn get_list_cards(&self, list_id: &str) -> Result<Vec<Card>, Box<Error>> {
+        let cards : Vec<Card> = self.client.get(&format!("/lists/{}/cards?filter=open&fields={}", list_id, CARD_FIELDS))?;
+        Ok(cards)
+    }
+
+    pub fn find_membership(&self, username: &str) -> Result<Option<Membership>, Box<Error>> {
+        let username = username.trim_start_matches('@').to_lowercase();
+        Ok(self.get_memberships()?.into_iter().find(|membership| membership.member.username.to_lowercase() == username))
     }
 }
 
 pub struct CardHandler {
-    http_token_parameters: String,
-    http_client: Client
+    client: Arc<TrelloApi>,
+    card_cache: TtlCache<Card>,
+    member_cache: TtlCache<Member>,
+    list_cache: TtlCache<List>
 }
 
 impl CardHandler {
-    pub fn new(trello_api_key: &str, trello_oauth_token: &str) -> CardHandler {
+    pub fn new(client: Arc<TrelloApi>) -> CardHandler {
         CardHandler {
-            http_token_parameters: format!("key={}&token={}", trello_api_key, trello_oauth_token).to_string(),
-            http_client: Client::new()
+            client: client,
+            card_cache: TtlCache::new(CARD_CACHE_TTL),
+            member_cache: TtlCache::new(MEMBER_CACHE_TTL),
+            list_cache: TtlCache::new(LIST_CACHE_TTL)
+        }
+    }
+
+    fn card_route(card_id: &str) -> String {
+        // Nested resources let us pull the card, its members and its creator in a single request
+        format!("/cards/{}?fields={}&members=true&member_fields={}&actions=createCard,copyCard&action_memberCreator_fields={}",
+            card_id, CARD_FIELDS, MEMBER_FIELDS, MEMBER_FIELDS)
+    }
+
+    // Trello splits the batched URLs on commas after decoding them, so these routes can't have comma-separated lists in them.
+    // The card comes with its default fields, and the two kinds of action its creator can come from are asked for separately.
+    fn card_batch_routes(card_id: &str) -> Vec<String> {
+        vec![format!("/cards/{}?members=true&actions=createCard", card_id), format!("/cards/{}/actions?filter=copyCard", card_id)]
+    }
+
+    fn get_batch(&self, routes: &[String]) -> Result<Vec<Option<Value>>, Box<Error>> {
+        debug_assert!(routes.iter().all(|route| !route.contains(',')), "Batched routes can't contain commas");
+
+        // Each route is encoded on its own, since the routes themselves contain ampersands
+        let encoded_routes : Vec<String> = routes.iter()
+            .map(|route| byte_serialize(route.as_bytes()).collect::<String>())
+            .collect();
+
+        // Trello returns one object per route, keyed by the status code of that route
+        let results : Vec<HashMap<String, Value>> = self.client.get(&format!("/batch?urls={}", encoded_routes.join(",")))?;
+        Ok(results.into_iter().map(|mut result| result.remove("200")).collect())
+    }
+
+    fn cache_card(&self, card: Card) {
+        for member in card.members.iter() {
+            self.member_cache.insert(&member.id, member.clone());
+        }
+        for action in card.actions.iter() {
+            self.member_cache.insert(&action.creator.id, action.creator.clone());
+        }
+        self.card_cache.insert(&card.id.clone(), card);
+    }
+
+    pub fn prefetch_cards(&self, card_ids: &[&str]) -> Result<(), Box<Error>> {
+        let mut missing_ids : Vec<&str> = Vec::new();
+        for card_id in card_ids {
+            if !self.card_cache.contains(card_id) && !missing_ids.contains(card_id) {
+                missing_ids.push(*card_id);
+            }
         }
+
+        if missing_ids.is_empty() {
+            return Ok(());
+        }
+
+        info!("Prefetching {} cards ...", missing_ids.len());
+
+        for chunk in missing_ids.chunks(BATCH_LIMIT / 2) {
+            let routes : Vec<String> = chunk.iter().flat_map(|card_id| CardHandler::card_batch_routes(card_id)).collect();
+            let results = self.get_batch(&routes)?;
+            for card_results in results.chunks(2) {
+                if let Some(ref card_value) = card_results[0] {
+                    let mut card : Card = from_value(card_value.clone())?;
+                    if card.actions.is_empty() {
+                        if let Some(&Some(ref copy_actions)) = card_results.get(1) {
+                            card.actions = from_value(copy_actions.clone())?;
+                        }
+                    }
+                    self.cache_card(card);
+                }
+            }
+        }
+
+        self.card_cache.purge_expired();
+        self.member_cache.purge_expired();
+
+        Ok(())
     }
 
     pub fn get_card(&self, card_id: &str) -> Result<Card, Box<Error>> {
+        if let Some(card) = self.card_cache.get(card_id) {
+            return Ok(card);
+        }
+
         info!("Fetching card ... {}", card_id);
 
-        let card_url = format!("{}/cards/{}?fields=all&{}",
-            API_URL, card_id, self.http_token_parameters);
-        let mut card_resp = self.http_client
-            .get(&card_url)
-            .header(UserAgent::new(USER_AGENT.to_string()))
-            .send()?;
-        let card : Card = card_resp.json()?;
+        let card : Card = self.client.get(&CardHandler::card_route(card_id))?;
+
+        self.cache_card(card.clone());
 
         Ok(card)
     }
 
-    pub fn get_card_members(&self, card: &Card) -> Result<Vec<Member>, Box<Error>> {
-        info!("Fetching card members ...");
+    pub fn find_card(&self, card_ref: &str) -> Result<Option<Card>, Box<Error>> {
+        // Accept full card URLs (https://trello.com/c/[SHORTLINK]/[TITLE]) as well as IDs and short links
+        let card_ref = match card_ref.find("/c/") {
+            Some(index) => card_ref[index + 3..].split('/').next().unwrap_or(""),
+            None => card_ref
+        };
+        let card_ref = card_ref.trim_matches(|c| c == '<' || c == '>');
 
-        let mut members = Vec::new();
-        for member_id in card.id_members.clone() {
-            let member_url = format!("{}/members/{}?fields=all&{}", API_URL, member_id, self.http_token_parameters);
-            let mut member_resp = self.http_client
-                .get(&member_url)
-                .header(UserAgent::new(USER_AGENT.to_string()))
-                .send()?;
-            let member : Member = member_resp.json()?;
+        // The reference ends up in the route, so anything that isn't an ID or a short link can't be a card
+        if card_ref.is_empty() || !card_ref.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Ok(None);
+        }
 
-            members.push(member);
+        match self.get_card(card_ref) {
+            Ok(card) => Ok(Some(card)),
+            Err(e) => {
+                // Trello answers malformed IDs with a 400 and unknown ones with a 404
+                if let Some(&TrelloError::Status { status, .. }) = e.downcast_ref::<TrelloError>() {
+                    if status == 400 || status == 404 {
+                        return Ok(None);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub fn get_list(&self, list_id: &str) -> Result<List, Box<Error>> {
+        if let Some(list) = self.list_cache.get(list_id) {
+            return Ok(list);
         }
 
-        info!("Fetching card creator ...");
+        info!("Fetching list ... {}", list_id);
+
+        let list : List = self.client.get(&format!("/lists/{}?fields={}", list_id, LIST_FIELDS))?;
+
+        self.list_cache.insert(list_id, list.clone());
+
+        Ok(list)
+    }
+
+    pub fn get_checklists(&self, card_id: &str) -> Result<Vec<Checklist>, Box<Error>> {
+        let checklists : Vec<Checklist> = self.client.get(&format!("/cards/{}/checklists", card_id))?;
+        Ok(checklists)
+    }
+
+    pub fn get_attachments(&self, card_id: &str) -> Result<Vec<Attachment>, Box<Error>> {
+        let attachments : Vec<Attachment> = self.client.get(&format!("/cards/{}/attachments", card_id))?;
+        Ok(attachments)
+    }
+
+    pub fn get_card_history(&self, card_id: &str) -> Result<Vec<Action>, Box<Error>> {
+        info!("Fetching card history ... {}", card_id);
+
+        // Trello returns the newest actions first, so flip them around to read like a timeline
+        let mut actions : Vec<Action> = self.client.get(&format!("/cards/{}/actions?filter={}&memberCreator_fields={}", card_id, HISTORY_ACTIONS, MEMBER_FIELDS))?;
+        actions.reverse();
+        Ok(actions)
+    }
+
+    pub fn move_card(&self, card_id: &str, list_id: &str) -> Result<Card, Box<Error>> {
+        info!("Moving card {} to list {} ...", card_id, list_id);
+
+        self.card_cache.remove(card_id);
+        let card : Card = self.client.put(&format!("/cards/{}?idList={}", card_id, list_id))?;
+        Ok(card)
+    }
+
+    pub fn add_member(&self, card_id: &str, member_id: &str) -> Result<(), Box<Error>> {
+        info!("Adding member {} to card {} ...", member_id, card_id);
+
+        self.card_cache.remove(card_id);
+        let _ : Value = self.client.post(&format!("/cards/{}/idMembers?value={}", card_id, member_id))?;
+        Ok(())
+    }
+
+    pub fn add_comment(&self, card_id: &str, text: &str) -> Result<(), Box<Error>> {
+        info!("Commenting on card {} ...", card_id);
+
+        let encoded_text : String = byte_serialize(text.as_bytes()).collect();
+        let _ : Value = self.client.post(&format!("/cards/{}/actions/comments?text={}", card_id, encoded_text))?;
+        Ok(())
+    }
+
+    pub fn get_card_members(&self, card: &Card) -> Result<Vec<Member>, Box<Error>> {
+        let mut members : Vec<Member> = card.members.clone();
 
-        let creator_url = format!("{}/cards/{}?fields=id&actions=createCard,copyCard&action_fields=idMemberCreator,memberCreator&action_memberCreator_fields=all&{}",
-            API_URL, card.id, self.http_token_parameters);
+        // Cards fetched without nested members only carry member IDs, so resolve whatever is not cached in one batch
+        if members.len() < card.id_members.len() {
+            let mut missing_ids = Vec::new();
+            for member_id in card.id_members.iter() {
+                if members.iter().any(|m| m.id == *member_id) {
+                    continue;
+                }
+                match self.member_cache.get(member_id) {
+                    Some(member) => members.push(member),
+                    None => missing_ids.push(member_id.clone())
+                }
+            }
 
-        let mut creator_resp = self.http_client
-            .get(&creator_url)
-            .header(UserAgent::new(USER_AGENT.to_string()))
-            .send()?;
-        let result : Value = creator_resp.json()?;
-        let create_actions : &Vec<Value> = result.get("actions").unwrap().as_array().unwrap();
-        if create_actions.iter().count() > 0 {
-            let create_action : &Value = create_actions.iter().nth(0).unwrap();
-            let creator : Member = from_value(create_action.get("memberCreator").unwrap().clone()).unwrap();
+            if !missing_ids.is_empty() {
+                info!("Fetching {} card members ...", missing_ids.len());
 
-            if !(members.iter().any(|m| *m == creator)) {
-                members.push(creator);
+                for chunk in missing_ids.chunks(BATCH_LIMIT) {
+                    let routes : Vec<String> = chunk.iter()
+                        .map(|member_id| format!("/members/{}", member_id))
+                        .collect();
+                    for result in self.get_batch(&routes)? {
+                        if let Some(member_value) = result {
+                            let member : Member = from_value(member_value)?;
+                            self.member_cache.insert(&member.id, member.clone());
+                            members.push(member);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The card's creator comes along with the nested createCard/copyCard actions
+        if let Some(create_action) = card.actions.iter().nth(0) {
+            if !(members.iter().any(|m| *m == create_action.creator)) {
+                members.push(create_action.creator.clone());
             }
         }
 
@@ -127,3 +468,294 @@ impl CardHandler {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use super::*;
+    use trello_client::TrelloApi;
+    use mock_board::query_param;
+    use trello_fake::FakeTrello;
+    use trello_listeners::ActionListener;
+    use trello_models::Action;
+
+    use reqwest::Method;
+
+    const BOARD_ID: &'static str = "5a0000000000000000000001";
+    const CARD_ID: &'static str = "5a3000000000000000000001";
+
+    struct RecordingListener {
+        name: &'static str,
+        filter: &'static str,
+        fails: bool,
+        actions: Rc<RefCell<Vec<String>>>
+    }
+
+    impl ActionListener for RecordingListener {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn get_filtered_actions(&self) -> &str {
+            self.filter
+        }
+
+        fn on_action(&self, action: &Action) -> Result<(), Box<Error>> {
+            self.actions.borrow_mut().push(format!("{} {}", self.name, action.id));
+            if self.fails {
+                return Err(From::from("listener failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn recording_listener(name: &'static str, filter: &'static str, fails: bool, actions: &Rc<RefCell<Vec<String>>>) -> Box<ActionListener> {
+        Box::new(RecordingListener {
+            name: name,
+            filter: filter,
+            fails: fails,
+            actions: Rc::clone(actions)
+        })
+    }
+
+    fn fake_trello() -> (Arc<FakeTrello>, Arc<TrelloApi>) {
+        let trello = Arc::new(FakeTrello::new());
+        let client : Arc<TrelloApi> = trello.clone();
+        (trello, client)
+    }
+
+    #[test]
+    fn poll_relays_actions_oldest_first() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, &format!("/boards/{}/actions", BOARD_ID), "board_actions.json");
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.add_listener(recording_listener("recorder", "updateCard,commentCard", false, &actions));
+        board_handler.poll().unwrap();
+
+        assert_eq!(*actions.borrow(), vec!["recorder 5b1000000000000000000001", "recorder 5b1000000000000000000002", "recorder 5b1000000000000000000003"]);
+        assert!(trello.requests()[0].contains("filter=updateCard,commentCard"));
+    }
+
+    #[test]
+    fn poll_asks_for_every_listeners_actions_once() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, &format!("/boards/{}/actions", BOARD_ID), "board_actions.json");
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.add_listener(recording_listener("moves", "updateCard", false, &actions));
+        board_handler.add_listener(recording_listener("comments", "commentCard,updateCard:idList", false, &actions));
+        board_handler.poll().unwrap();
+
+        assert!(trello.requests()[0].contains("filter=updateCard,commentCard,updateCard:idList&"));
+        assert_eq!(*actions.borrow(), vec![
+            "moves 5b1000000000000000000001", "comments 5b1000000000000000000001",
+            "moves 5b1000000000000000000002", "comments 5b1000000000000000000002",
+            "comments 5b1000000000000000000003"
+        ]);
+    }
+
+    #[test]
+    fn failing_listeners_do_not_stop_the_others() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, &format!("/boards/{}/actions", BOARD_ID), "board_actions.json");
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.add_listener(recording_listener("broken", "commentCard", true, &actions));
+        board_handler.add_listener(recording_listener("comments", "commentCard", false, &actions));
+        board_handler.poll().unwrap();
+
+        assert_eq!(*actions.borrow(), vec!["broken 5b1000000000000000000003", "comments 5b1000000000000000000003"]);
+    }
+
+    #[test]
+    fn failed_actions_are_retried_by_their_listener() {
+        let (trello, client) = fake_trello();
+        let path = format!("/boards/{}/actions", BOARD_ID);
+        trello.respond_with_fixture(Method::Get, &path, "board_actions.json");
+        trello.respond(Method::Get, &path, "[]");
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.add_listener(recording_listener("broken", "commentCard", true, &actions));
+        board_handler.add_listener(recording_listener("comments", "commentCard", false, &actions));
+        for _ in 0..MAX_ACTION_ATTEMPTS + 1 {
+            board_handler.poll().unwrap();
+        }
+
+        // Only the failing listener sees the action again, until it runs out of attempts
+        let handled = actions.borrow();
+        assert_eq!(handled.iter().filter(|handled| *handled == "broken 5b1000000000000000000003").count(), MAX_ACTION_ATTEMPTS as usize);
+        assert_eq!(handled.iter().filter(|handled| *handled == "comments 5b1000000000000000000003").count(), 1);
+    }
+
+    #[test]
+    fn poll_asks_for_actions_since_an_iso_date() {
+        let (trello, client) = fake_trello();
+        trello.respond(Method::Get, &format!("/boards/{}/actions", BOARD_ID), "[]");
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.state.lock().unwrap().since = Utc.ymd(2018, 6, 1).and_hms_milli(12, 15, 0, 123);
+        board_handler.add_listener(recording_listener("recorder", "updateCard", false, &actions));
+        board_handler.poll().unwrap();
+
+        assert_eq!(trello.requests(), vec![format!("GET /boards/{}/actions?filter=updateCard&since=2018-06-01T12:15:00.123Z", BOARD_ID)]);
+    }
+
+    #[test]
+    fn poll_without_listeners_asks_for_nothing() {
+        let (trello, client) = fake_trello();
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.poll().unwrap();
+
+        assert!(trello.requests().is_empty());
+    }
+
+    #[test]
+    fn failed_poll_is_retried_from_the_same_point() {
+        let (trello, client) = fake_trello();
+        let path = format!("/boards/{}/actions", BOARD_ID);
+        trello.fail(Method::Get, &path, 500);
+        trello.respond(Method::Get, &path, "[]");
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.add_listener(recording_listener("recorder", "updateCard", false, &actions));
+        assert!(board_handler.poll().is_err());
+        board_handler.poll().unwrap();
+
+        let requests = trello.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0], requests[1]);
+    }
+
+    #[test]
+    fn restarted_pollers_carry_on_where_they_stopped() {
+        let (trello, client) = fake_trello();
+        let path = format!("/boards/{}/actions", BOARD_ID);
+        trello.respond_with_fixture(Method::Get, &path, "board_actions.json");
+        trello.respond(Method::Get, &path, "[]");
+
+        let state = PollState::new();
+        state.lock().unwrap().since = Utc.ymd(2018, 6, 1).and_hms(12, 15, 0);
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut board_handler = BoardHandler::with_state(BOARD_ID, Arc::clone(&client), Arc::clone(&state));
+            board_handler.add_listener(recording_listener("broken", "commentCard", true, &actions));
+            board_handler.poll().unwrap();
+        }
+
+        let mut board_handler = BoardHandler::with_state(BOARD_ID, client, Arc::clone(&state));
+        board_handler.add_listener(recording_listener("broken", "commentCard", true, &actions));
+        board_handler.poll().unwrap();
+
+        // The new handler asks for what came after the last poll, and still retries what the old one failed on
+        let requests = trello.requests();
+        assert!(requests[0].ends_with("since=2018-06-01T12:15:00.000Z"));
+        assert!(!requests[1].ends_with("since=2018-06-01T12:15:00.000Z"));
+        assert_eq!(*actions.borrow(), vec!["broken 5b1000000000000000000003", "broken 5b1000000000000000000003"]);
+    }
+
+    #[test]
+    fn comments_are_asked_for_since_an_iso_date() {
+        let (trello, client) = fake_trello();
+        trello.respond(Method::Get, &format!("/boards/{}/actions", BOARD_ID), "[]");
+
+        let board_handler = BoardHandler::new(BOARD_ID, client);
+        board_handler.get_comments(Utc.ymd(2018, 6, 1).and_hms(12, 15, 0)).unwrap();
+
+        assert_eq!(trello.requests(), vec![format!("GET /boards/{}/actions?filter=commentCard&since=2018-06-01T12:15:00.000Z&limit=1000", BOARD_ID)]);
+    }
+
+    #[test]
+    fn prefetched_cards_are_not_fetched_again() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, "/batch", "batch_cards.json");
+
+        let card_handler = CardHandler::new(client);
+        card_handler.prefetch_cards(&[CARD_ID, CARD_ID]).unwrap();
+        let card = card_handler.get_card(CARD_ID).unwrap();
+
+        assert_eq!(card.name, "Library hours extended");
+        assert_eq!(trello.requests().len(), 1);
+        assert_eq!(trello.requests_to(Method::Get, "/batch").len(), 1);
+    }
+
+    #[test]
+    fn batched_routes_have_no_commas_in_them() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, "/batch", "batch_cards.json");
+
+        let card_handler = CardHandler::new(client);
+        card_handler.prefetch_cards(&[CARD_ID]).unwrap();
+
+        // The commas between routes are the only ones left once the whole list is decoded
+        let request = trello.requests()[0].clone();
+        let urls = query_param(request.splitn(2, '?').nth(1).unwrap(), "urls").unwrap();
+        assert_eq!(urls.split(',').collect::<Vec<&str>>(), vec![
+            format!("/cards/{}?members=true&actions=createCard", CARD_ID),
+            format!("/cards/{}/actions?filter=copyCard", CARD_ID)
+        ]);
+    }
+
+    #[test]
+    fn find_card_accepts_urls_and_short_links() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, "/cards/CrD1sHrt", "card.json");
+
+        let card_handler = CardHandler::new(client);
+        let card = card_handler.find_card("<https://trello.com/c/CrD1sHrt/1-library-hours-extended>").unwrap();
+
+        assert_eq!(card.map(|card| card.id), Some(CARD_ID.to_string()));
+        assert_eq!(trello.requests_to(Method::Get, "/cards/CrD1sHrt").len(), 1);
+    }
+
+    #[test]
+    fn find_card_returns_none_for_unknown_cards() {
+        let (trello, client) = fake_trello();
+        trello.fail(Method::Get, "/cards/nope", 404);
+
+        let card_handler = CardHandler::new(client);
+        assert!(card_handler.find_card("nope").unwrap().is_none());
+    }
+
+    #[test]
+    fn find_card_only_looks_up_ids_and_short_links() {
+        let (trello, client) = fake_trello();
+        let card_handler = CardHandler::new(client);
+        for card_ref in ["../boards/5a0000000000000000000001", "CrD1sHrt?fields=all", "a%2Fb", "", "https://trello.com/c/"].iter() {
+            assert!(card_handler.find_card(card_ref).unwrap().is_none());
+        }
+        assert!(trello.requests().is_empty());
+    }
+
+    #[test]
+    fn card_members_include_the_creator() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Get, &format!("/cards/{}", CARD_ID), "card.json");
+
+        let card_handler = CardHandler::new(client);
+        let card = card_handler.get_card(CARD_ID).unwrap();
+        let usernames : Vec<String> = card_handler.get_card_members(&card).unwrap().into_iter().map(|member| member.username).collect();
+
+        assert_eq!(usernames, vec!["wendywriter", "eddieeditor"]);
+    }
+
+    #[test]
+    fn move_card_puts_the_new_list() {
+        let (trello, client) = fake_trello();
+        trello.respond_with_fixture(Method::Put, &format!("/cards/{}", CARD_ID), "card.json");
+
+        let card_handler = CardHandler::new(client);
+        card_handler.move_card(CARD_ID, "5a2000000000000000000002").unwrap();
+
+        assert_eq!(trello.requests(), vec![format!("PUT /cards/{}?idList=5a2000000000000000000002", CARD_ID)]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::error::Error;
+use std::io::Read;
+use std::{fmt, thread, cmp};
+
+use reqwest;
+use reqwest::{Client, Method, Response, StatusCode};
+use reqwest::header::UserAgent;
+use serde::de::DeserializeOwned;
+use serde_json;
+use rand;
+
+pub const API_URL: &'static str = "https://api.trello.com/1";
+const USER_AGENT: &'static str = "Mozilla/5.0 (Windows NT 5.1; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/46.0.2486.0 Safari/537.36 Edge/13.10586";
+const MAX_ATTEMPTS: u32 = 5;
+const BACKOFF_BASE_MILLIS: u64 = 500;
+const BACKOFF_MAX_MILLIS: u64 = 30000;
+// Trello's documented token limit is 100 requests per 10 second interval
+const DEFAULT_RATE_LIMIT_INTERVAL_MILLIS: u64 = 10000;
+const RATE_LIMIT_REMAINING_HEADERS: [&'static str; 2] = ["x-rate-limit-api-token-remaining", "x-rate-limit-api-key-remaining"];
+const RATE_LIMIT_INTERVAL_HEADER: &'static str = "x-rate-limit-api-token-interval-ms";
+
+#[derive(Debug)]
+pub enum TrelloError {
+    Http(reqwest::Error),
+    Status { status: u16, body: String },
+    RateLimited,
+    InvalidJson { body: String, error: serde_json::Error }
+}
+
+impl TrelloError {
+    fn is_transient(&self) -> bool {
+        match *self {
+            TrelloError::Http(_) | TrelloError::RateLimited => true,
+            TrelloError::Status { status, .. } => status >= 500,
+            TrelloError::InvalidJson { .. } => false
+        }
+    }
+}
+
+impl fmt::Display for TrelloError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrelloError::Http(ref error) => write!(f, "Trello request failed: {}", error),
+            TrelloError::Status { status, ref body } => write!(f, "Trello returned status {}: {}", status, body),
+            TrelloError::RateLimited => write!(f, "Trello rate limit exceeded"),
+            TrelloError::InvalidJson { ref body, ref error } => write!(f, "Trello returned invalid JSON ({}): {}", error, body)
+        }
+    }
+}
+
+impl Error for TrelloError {
+    fn description(&self) -> &str {
+        match *self {
+            TrelloError::Http(_) => "Trello request failed",
+            TrelloError::Status { .. } => "Trello returned an error status",
+            TrelloError::RateLimited => "Trello rate limit exceeded",
+            TrelloError::InvalidJson { .. } => "Trello returned invalid JSON"
+        }
+    }
+}
+
+impl From<reqwest::Error> for TrelloError {
+    fn from(error: reqwest::Error) -> TrelloError {
+        TrelloError::Http(error)
+    }
+}
+
+// Everything that talks to Trello goes through this, so tests can swap the real API out for recorded responses
+pub trait TrelloApi : Send + Sync {
+    fn send(&self, method: Method, route: &str) -> Result<String, TrelloError>;
+}
+
+impl TrelloApi {
+    pub fn get<T : DeserializeOwned>(&self, route: &str) -> Result<T, TrelloError> {
+        self.request(Method::Get, route)
+    }
+
+    pub fn put<T : DeserializeOwned>(&self, route: &str) -> Result<T, TrelloError> {
+        self.request(Method::Put, route)
+    }
+
+    pub fn post<T : DeserializeOwned>(&self, route: &str) -> Result<T, TrelloError> {
+        self.request(Method::Post, route)
+    }
+
+    fn request<T : DeserializeOwned>(&self, method: Method, route: &str) -> Result<T, TrelloError> {
+        let body = self.send(method, route)?;
+        serde_json::from_str(&body).map_err(|error| TrelloError::InvalidJson { body: body, error: error })
+    }
+}
+
+pub struct TrelloClient {
+    http_client: Client,
+    base_url: String,
+    http_token_parameters: String,
+    // Requests are held back until this instant once Trello reports that the current rate limit window is used up
+    blocked_until: Mutex<Option<Instant>>
+}
+
+impl TrelloClient {
+    pub fn new(base_url: &str, trello_api_key: &str, trello_oauth_token: &str) -> TrelloClient {
+        TrelloClient {
+            http_client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http_token_parameters: format!("key={}&token={}", trello_api_key, trello_oauth_token).to_string(),
+            blocked_until: Mutex::new(None)
+        }
+    }
+
+    fn send_with_retries(&self, method: Method, route: &str) -> Result<String, TrelloError> {
+        let separator = if route.contains("?") { "&" } else { "?" };
+        let url = format!("{}{}{}{}", self.base_url, route, separator, self.http_token_parameters);
+
+        let mut attempt = 0;
+        loop {
+            self.wait_for_rate_limit();
+
+            let result = self.http_client
+                .request(method.clone(), &url)
+                .header(UserAgent::new(USER_AGENT.to_string()))
+                .send()
+                .map_err(TrelloError::from)
+                .and_then(|resp| self.read_response(resp));
+
+            match result {
+                Ok(body) => return Ok(body),
+                Err(error) => {
+                    attempt += 1;
+
+                    // Creating things twice is worse than failing, so POSTs are only retried when Trello turned them away outright
+                    let retryable = match error {
+                        TrelloError::RateLimited => true,
+                        _ => error.is_transient() && method != Method::Post
+                    };
+                    if !retryable || attempt >= MAX_ATTEMPTS {
+                        return Err(error);
+                    }
+
+                    let backoff = TrelloClient::backoff(attempt);
+                    warn!("{} (attempt {} of {}), retrying in {}ms ...", error, attempt, MAX_ATTEMPTS, duration_millis(backoff));
+                    thread::sleep(backoff);
+                }
+            }
+        }
+    }
+
+    fn read_response(&self, mut resp: Response) -> Result<String, TrelloError> {
+        self.update_rate_limit(&resp);
+
+        let status = resp.status();
+        let mut body = String::new();
+        resp.read_to_string(&mut body).map_err(|error| TrelloError::Status {
+            status: status.as_u16(),
+            body: format!("<unreadable body: {}>", error)
+        })?;
+
+        if status == StatusCode::TooManyRequests {
+            self.block_for(Duration::from_millis(DEFAULT_RATE_LIMIT_INTERVAL_MILLIS));
+            return Err(TrelloError::RateLimited);
+        }
+        if !status.is_success() {
+            return Err(TrelloError::Status { status: status.as_u16(), body: body });
+        }
+
+        Ok(body)
+    }
+
+    fn update_rate_limit(&self, resp: &Response) {
+        let header_value = |name: &str| -> Option<u64> {
+            resp.headers().get_raw(name)
+                .and_then(|raw| raw.one())
+                .and_then(|bytes| String::from_utf8_lossy(bytes).trim().parse().ok())
+        };
+
+        let exhausted = RATE_LIMIT_REMAINING_HEADERS.iter().any(|name| header_value(name) == Some(0));
+        if exhausted {
+            let interval = header_value(RATE_LIMIT_INTERVAL_HEADER).unwrap_or(DEFAULT_RATE_LIMIT_INTERVAL_MILLIS);
+            warn!("Trello rate limit window exhausted, pausing requests for {}ms.", interval);
+            self.block_for(Duration::from_millis(interval));
+        }
+    }
+
+    fn block_for(&self, duration: Duration) {
+        let mut blocked_until = self.blocked_until.lock().unwrap();
+        let until = Instant::now() + duration;
+        if blocked_until.map_or(true, |current| current < until) {
+            *blocked_until = Some(until);
+        }
+    }
+
+    fn wait_for_rate_limit(&self) {
+        let until = *self.blocked_until.lock().unwrap();
+        if let Some(until) = until {
+            let now = Instant::now();
+            if until > now {
+                thread::sleep(until - now);
+            }
+        }
+    }
+
+    fn backoff(attempt: u32) -> Duration {
+        // Exponential backoff, capped, with +/- 50% jitter so that retries from several threads don't line up
+        let exponential = cmp::min(BACKOFF_BASE_MILLIS.saturating_mul(1 << cmp::min(attempt, 16)), BACKOFF_MAX_MILLIS);
+        let jitter = 0.5 + rand::random::<f64>();
+        Duration::from_millis((exponential as f64 * jitter) as u64)
+    }
+}
+
+impl TrelloApi for TrelloClient {
+    fn send(&self, method: Method, route: &str) -> Result<String, TrelloError> {
+        self.send_with_retries(method, route)
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1000000) as u64
+}
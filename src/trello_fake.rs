@@ -0,0 +1,88 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+use std::env;
+
+use trello_client::{TrelloApi, TrelloError};
+
+use reqwest::Method;
+
+// Replays recorded Trello responses, keyed by method and path (the query string is ignored), and keeps track of every request made
+pub struct FakeTrello {
+    responses: Mutex<HashMap<String, VecDeque<Result<String, u16>>>>,
+    responders: Mutex<HashMap<String, Box<Fn(&str) -> Result<String, u16> + Send>>>,
+    requests: Mutex<Vec<String>>
+}
+
+pub fn fixture(name: &str) -> String {
+    let path = format!("{}/fixtures/trello/{}", env::var("CARGO_MANIFEST_DIR").unwrap(), name);
+    let mut body = String::new();
+    File::open(&path).and_then(|mut file| file.read_to_string(&mut body)).expect(&format!("Missing fixture {}", path));
+    body
+}
+
+fn request_key(method: &Method, route: &str) -> String {
+    format!("{} {}", method, route.split('?').next().unwrap())
+}
+
+impl FakeTrello {
+    pub fn new() -> FakeTrello {
+        FakeTrello {
+            responses: Mutex::new(HashMap::new()),
+            responders: Mutex::new(HashMap::new()),
+            requests: Mutex::new(Vec::new())
+        }
+    }
+
+    // Responses to the same path are replayed in order, and the last one keeps being replayed after that
+    pub fn respond(&self, method: Method, path: &str, body: &str) {
+        self.responses.lock().unwrap().entry(request_key(&method, path)).or_insert(VecDeque::new()).push_back(Ok(body.to_string()));
+    }
+
+    pub fn respond_with_fixture(&self, method: Method, path: &str, name: &str) {
+        self.respond(method, path, &fixture(name));
+    }
+
+    pub fn fail(&self, method: Method, path: &str, status: u16) {
+        self.responses.lock().unwrap().entry(request_key(&method, path)).or_insert(VecDeque::new()).push_back(Err(status));
+    }
+
+    // Answers every request to a path by calling the responder with the full route, for responses that depend on state
+    pub fn respond_with<F>(&self, method: Method, path: &str, responder: F) where F : Fn(&str) -> Result<String, u16> + Send + 'static {
+        self.responders.lock().unwrap().insert(request_key(&method, path), Box::new(responder));
+    }
+
+    // Every request made so far, as "[METHOD] [ROUTE]" including the query string
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn requests_to(&self, method: Method, path: &str) -> Vec<String> {
+        let key = request_key(&method, path);
+        self.requests().into_iter().filter(|request| request_key_of(request) == key).collect()
+    }
+}
+
+fn request_key_of(request: &str) -> String {
+    request.split('?').next().unwrap().to_string()
+}
+
+impl TrelloApi for FakeTrello {
+    fn send(&self, method: Method, route: &str) -> Result<String, TrelloError> {
+        self.requests.lock().unwrap().push(format!("{} {}", method, route));
+
+        if let Some(responder) = self.responders.lock().unwrap().get(&request_key(&method, route)) {
+            return responder(route).map_err(|status| TrelloError::Status { status: status, body: format!("{} {}", method, route) });
+        }
+
+        let mut responses = self.responses.lock().unwrap();
+        let queue = match responses.get_mut(&request_key(&method, route)) {
+            Some(queue) => queue,
+            None => return Err(TrelloError::Status { status: 404, body: format!("No fixture for {} {}", method, route) })
+        };
+
+        let response = if queue.len() > 1 { queue.pop_front().unwrap() } else { queue[0].clone() };
+        response.map_err(|status| TrelloError::Status { status: status, body: format!("{} {}", method, route) })
+    }
+}
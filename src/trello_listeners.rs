@@ -1,79 +1,500 @@
-use std::sync::mpsc::Sender;
-use std::sync::{Mutex, Arc};
 use std::error::Error;
+use std::sync::Arc;
 
 use trello::CardHandler;
-use trello_models::Action;
-use mongodb::db::{Database, ThreadedDatabase};
+use trello_models::{Action, ListData};
+use history::{CardEvent, EventStore};
+use slack_transport::{Notification, NotificationCard};
+use sinks::NotificationSinks;
+use subscriptions::Subscriptions;
+use store::Store;
+use templates::{Templates, LanguagePreferences};
+use bson::Bson;
+
+const TRELLO_CARD_URL: &'static str = "https://trello.com/c";
+const RELAYED_COLLECTION: &'static str = "relayed_actions";
 
 pub trait ActionListener {
+    fn name(&self) -> &str;
     fn get_filtered_actions(&self) -> &str;
+    fn prefetch(&self, _actions : &[Action]) -> Result<(), Box<Error>> {
+        Ok(())
+    }
     fn on_action(&self, action : &Action) -> Result<(), Box<Error>>;
 }
 
+fn get_list_name(card_handler: &CardHandler, list_data: &ListData) -> Result<String, Box<Error>> {
+    // Actions normally embed the list's name, but fall back to the (cached) list itself if they don't
+    if let Some(ref name) = list_data.name {
+        return Ok(name.clone());
+    }
+    let list = card_handler.get_list(&list_data.id)?;
+    Ok(list.name)
+}
+
+fn prefetch_moved_cards(card_handler: &CardHandler, actions : &[Action]) -> Result<(), Box<Error>> {
+    // Pull every moved card in as few requests as possible before the actions are handled one by one
+    let card_ids : Vec<&str> = actions.iter()
+        .filter(|action| action.data.list_move().is_some())
+        .filter_map(|action| action.data.card().map(|card| &card.id[..]))
+        .collect();
+    card_handler.prefetch_cards(&card_ids)
+}
+
+// Keeps every card action in the audit log that history, export and analytics are built from
+pub struct HistoryListener {
+    events: EventStore,
+    card_handler: Arc<CardHandler>
+}
+
+impl HistoryListener {
+    pub fn new(events: EventStore, card_handler: Arc<CardHandler>) -> HistoryListener {
+        HistoryListener {
+            events: events,
+            card_handler: card_handler
+        }
+    }
+
+    // Falls back to the list's ID when the action has no name for it and the list can't be fetched either
+    fn list_name(&self, list_data: &ListData) -> String {
+        get_list_name(&self.card_handler, list_data).unwrap_or_else(|e| {
+            warn!("Could not look up list {}: {}", list_data.id, e);
+            list_data.id.clone()
+        })
+    }
+}
+
+impl ActionListener for HistoryListener {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn get_filtered_actions(&self) -> &str {
+        "createCard,copyCard,updateCard"
+    }
+
+    fn prefetch(&self, actions : &[Action]) -> Result<(), Box<Error>> {
+        prefetch_moved_cards(&self.card_handler, actions)
+    }
+
+    fn on_action(&self, action : &Action) -> Result<(), Box<Error>> {
+        let mut event = CardEvent::from_action(action);
+
+        // Anything looked up on Trello only adds to what the action carries, so a failed lookup never costs the event itself
+        if let (Some(card_data), Some((list_before, list_after))) = (action.data.card(), action.data.list_move()) {
+            event.from_list = Some(self.list_name(list_before));
+            event.to_list = Some(self.list_name(list_after));
+
+            // Moves also keep the card's members at the time, so stats can credit writers
+            match self.card_handler.get_card(&card_data.id).and_then(|card| self.card_handler.get_card_members(&card)) {
+                Ok(members) => event.members = members.iter().map(|member| member.username.clone()).collect(),
+                Err(e) => warn!("Recording action {} without the members of card {}: {}", action.id, card_data.id, e)
+            }
+        }
+
+        self.events.record(&event)
+    }
+}
+
 pub struct RelayActionListener {
-    db: Database,
-    card_handler: CardHandler,
-    buffer_tx: Sender<String>,
-    buffer_count: Arc<Mutex<u8>>
+    store: Arc<Store>,
+    events: EventStore,
+    subscriptions: Subscriptions,
+    languages: LanguagePreferences,
+    card_handler: Arc<CardHandler>,
+    sinks: NotificationSinks,
+    templates: Templates
 }
 
 impl RelayActionListener {
-    pub fn new(db: Database, card_handler: CardHandler, buffer_tx: Sender<String>, buffer_count: Arc<Mutex<u8>>) -> RelayActionListener {
+    pub fn new(store: Arc<Store>, card_handler: Arc<CardHandler>, sinks: NotificationSinks, templates: Templates) -> RelayActionListener {
         RelayActionListener {
-            db: db,
+            events: EventStore::new(Arc::clone(&store)),
+            subscriptions: Subscriptions::new(Arc::clone(&store)),
+            languages: LanguagePreferences::new(Arc::clone(&store)),
+            store: store,
             card_handler: card_handler,
-            buffer_tx: buffer_tx,
-            buffer_count: buffer_count
+            sinks: sinks,
+            templates: templates
         }
     }
+
+    // Who has already been notified about an action that failed partway, so that retrying it doesn't notify them twice
+    fn already_notified(&self, action_id: &str) -> Result<Vec<String>, Box<Error>> {
+        let rdoc = self.store.find_one(RELAYED_COLLECTION, doc! {
+            "action": action_id
+        })?;
+        Ok(rdoc.and_then(|rdoc| rdoc.get_array("recipients").ok().map(|recipients| {
+            recipients.iter().filter_map(|recipient| recipient.as_str().map(|recipient| recipient.to_string())).collect()
+        })).unwrap_or(Vec::new()))
+    }
+
+    fn mark_notified(&self, action_id: &str, recipient: &str) -> Result<(), Box<Error>> {
+        self.store.update_one(RELAYED_COLLECTION, doc! {
+            "action": action_id
+        }, doc! {
+            "$addToSet": { "recipients": recipient }
+        }, true)?;
+        Ok(())
+    }
 }
 
 impl ActionListener for RelayActionListener {
+    fn name(&self) -> &str {
+        "relay"
+    }
+
     fn get_filtered_actions(&self) -> &str {
         return &"updateCard";
     }
 
+    fn prefetch(&self, actions : &[Action]) -> Result<(), Box<Error>> {
+        prefetch_moved_cards(&self.card_handler, actions)
+    }
+
     fn on_action(&self, action : &Action) -> Result<(), Box<Error>> {
+        let notified = self.already_notified(&action.id)?;
+        let mut recipients = notified.clone();
+
         // Make sure that we only capture when a card is moved between lists
-        if let Some(list_before) = action.data.get("listBefore") {
-            let card = self.card_handler.get_card(action.data.get("card").unwrap().get("id").unwrap().as_str().unwrap())?;
+        if let (Some(card_data), Some((list_before, list_after))) = (action.data.card(), action.data.list_move()) {
+            // The action already carries the card's name, so the card itself is only needed for its members
+            let card = self.card_handler.get_card(&card_data.id)?;
             let card_members = self.card_handler.get_card_members(&card)?;
-            let card_title = card.name;
+            let card_title = if card_data.name.is_empty() { &card.name } else { &card_data.name };
+            let card_short_link = if card_data.short_link.is_empty() { &card.id } else { &card_data.short_link };
 
-            let list_before_name = list_before.get("name").unwrap().as_str().unwrap();
-            let list_after = action.data.get("listAfter").unwrap();
-            let list_after_name = list_after.get("name").unwrap().as_str().unwrap();
+            let card_url = format!("{}/{}", TRELLO_CARD_URL, card_short_link);
+            let board_name = action.board.as_ref().map(|board| &board.name[..]).unwrap_or("");
+            let list_before_name = get_list_name(&self.card_handler, list_before)?;
+            let list_after_name = get_list_name(&self.card_handler, list_after)?;
 
             info!("Card \"{}\" was moved from \"{}\" to \"{}\".", card_title, list_before_name, list_after_name);
 
             for member in card_members {
                 info!("Member \"{}\" is associated with this card.", &member.username);
 
+                // Each recipient gets the notification in their own language
+                let notification = |language: &str| Notification {
+                    channel: String::new(),
+                    text: self.templates.in_language(language).render("notify.moved", &[
+                        ("card", card_title),
+                        ("from", &list_before_name),
+                        ("to", &list_after_name),
+                        ("url", &card_url),
+                        ("board", board_name),
+                        ("actor", &action.creator.username),
+                        ("writer", &member.username)
+                    ]),
+                    language: language.to_string(),
+                    card: Some(NotificationCard {
+                        id: card.id.clone(),
+                        url: card_url.clone(),
+                        writer: member.username.clone(),
+                        snooze: false
+                    })
+                };
+
                 // If any Slack user is tracking this Trello user, find all Slack DM channel IDs through MongoDB and send a message to each one
-                if let Some(tdoc) = self.db.collection("trello").find_one(Some(doc! {
+                if let Some(tdoc) = self.store.find_one("trello", doc! {
                     "name": &member.username
-                }), None)? {
+                })? {
                     let trackers = tdoc.get_array("trackers").unwrap();
                     for tracker in trackers {
+                        if notified.iter().any(|recipient| Bson::String(recipient.clone()) == *tracker) {
+                            continue;
+                        }
+
                         // Tracker refers to a slack user that must exist
-                        let sdoc = self.db.collection("slack").find_one(Some(doc! {
+                        let sdoc = self.store.find_one("slack", doc! {
                             "uid": tracker.as_str().unwrap()
-                        }), None)?.unwrap();
+                        })?.unwrap();
                         let channel = sdoc.get_str("cid").unwrap();
 
-                        // Send the message by passing it to our mpsc sender
-                        let message = format!("Your card _{}_ has been moved from *{}* to *{}*.", card_title, list_before_name, list_after_name);
-                        self.buffer_tx.send(format!("{}|{}", channel, message))?;
+                        // Trackers can mute individual cards, either by ID or by short link
+                        if let Ok(muted) = sdoc.get_array("muted") {
+                            if muted.iter().any(|m| *m == Bson::String(card.id.clone()) || *m == Bson::String(card_short_link.to_string())) {
+                                info!("Tracker {} has muted this card.", tracker.as_str().unwrap());
+                                continue;
+                            }
+                        }
+
+                        let language = self.languages.get(tracker.as_str().unwrap())?;
+                        self.sinks.deliver("slack", channel, &notification(&language))?;
+                        self.mark_notified(&action.id, tracker.as_str().unwrap())?;
 
-                        // Increment the buffer count to notify the webhook that a flush needs to happen
-                        let mut message_count = self.buffer_count.lock().unwrap();
-                        *message_count += 1;
+                        recipients.push(tracker.as_str().unwrap().to_string());
+                    }
+                }
+
+                // Anyone else subscribed to this member is reached through their own sink, and one failing doesn't stop the rest.
+                // They hear in the language of whoever set the subscription up.
+                for subscription in self.subscriptions.for_member(&member.username)? {
+                    if notified.contains(&subscription.recipient()) {
+                        continue;
+                    }
+                    let language = self.languages.get(&subscription.creator)?;
+                    match self.sinks.deliver(&subscription.sink, &subscription.target, &notification(&language)) {
+                        Ok(()) => {
+                            self.mark_notified(&action.id, &subscription.recipient())?;
+                            recipients.push(subscription.recipient());
+                        }
+                        Err(e) => warn!("Failed to notify {} about card {}: {}", subscription.recipient(), card.id, e)
                     }
                 }
             }
         }
 
+        // The history listener records the action itself, this only adds who ended up being notified about it
+        if !recipients.is_empty() {
+            self.events.set_recipients(&action.id, &recipients)?;
+        }
+        self.store.delete_many(RELAYED_COLLECTION, doc! {
+            "action": &action.id
+        })?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::error::Error;
+    use std::fs::File;
+    use std::io::Write;
+    use std::rc::Rc;
+    use std::sync::{mpsc, Arc};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::Receiver;
+
+    use super::*;
+    use memory_store::MemoryStore;
+    use sinks::{NotificationSink, SlackSink};
+    use slack_transport::{NotificationSender, NOTIFICATION_BUFFER_SIZE};
+    use subscriptions::Subscription;
+    use templates::Templates;
+    use trello_client::TrelloApi;
+    use trello_fake::{FakeTrello, fixture};
+
+    use reqwest::Method;
+    use serde_json;
+
+    const CARD_ID: &'static str = "5a3000000000000000000001";
+
+    // Keeps what it was asked to deliver, or fails every delivery for the "broken" sink and to the failing targets
+    struct RecordingSink {
+        name: &'static str,
+        deliveries: Rc<RefCell<Vec<String>>>,
+        failing: Rc<RefCell<Vec<String>>>
+    }
+
+    impl NotificationSink for RecordingSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn check_target(&self, _target: &str) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        fn deliver(&self, target: &str, notification: &Notification) -> Result<(), Box<Error>> {
+            if self.name == "broken" || self.failing.borrow().iter().any(|failing| failing == target) {
+                return Err(From::from("sink is down"));
+            }
+            self.deliveries.borrow_mut().push(format!("{} {}", target, notification.text));
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        store: Arc<MemoryStore>,
+        trello: Arc<FakeTrello>,
+        history: HistoryListener,
+        listener: RelayActionListener,
+        notifications: Receiver<Notification>,
+        deliveries: Rc<RefCell<Vec<String>>>,
+        actions: Vec<Action>
+    }
+
+    fn setup(muted: Vec<&str>) -> Fixture {
+        setup_with_templates(muted, Templates::defaults())
+    }
+
+    fn setup_with_templates(muted: Vec<&str>, templates: Templates) -> Fixture {
+        let muted = Bson::Array(muted.into_iter().map(|card| Bson::String(card.to_string())).collect());
+        let store = Arc::new(MemoryStore::new());
+        store.insert_one("trello", doc! { "name": "wendywriter", "trackers": ["U1"] }).unwrap();
+        store.insert_one("slack", doc! { "uid": "U1", "cid": "D1", "tracking": "wendywriter", "muted": muted }).unwrap();
+
+        let trello = Arc::new(FakeTrello::new());
+        trello.respond_with_fixture(Method::Get, &format!("/cards/{}", CARD_ID), "card.json");
+        trello.respond_with_fixture(Method::Get, "/batch", "batch_cards.json");
+
+        let (tx, rx) = mpsc::sync_channel(NOTIFICATION_BUFFER_SIZE);
+        let client : Arc<TrelloApi> = trello.clone();
+        let card_handler = Arc::new(CardHandler::new(client));
+        let history = HistoryListener::new(EventStore::new(store.clone()), Arc::clone(&card_handler));
+        let deliveries = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks = NotificationSinks::new();
+        sinks.add(Box::new(SlackSink::new(NotificationSender::new(tx, Arc::new(AtomicUsize::new(0))))));
+        let failing = Rc::new(RefCell::new(Vec::new()));
+        sinks.add(Box::new(RecordingSink { name: "email", deliveries: Rc::clone(&deliveries), failing: Rc::clone(&failing) }));
+        sinks.add(Box::new(RecordingSink { name: "broken", deliveries: Rc::clone(&deliveries), failing: failing }));
+        let listener = RelayActionListener::new(store.clone(), card_handler, sinks, templates);
+
+        Fixture {
+            store: store,
+            trello: trello,
+            history: history,
+            listener: listener,
+            notifications: rx,
+            deliveries: deliveries,
+            actions: serde_json::from_str(&fixture("board_actions.json")).unwrap()
+        }
+    }
+
+    impl Fixture {
+        // Both listeners see each action, history first, the way the board handler runs them
+        fn handle(&self, action: &Action) {
+            self.history.on_action(action).unwrap();
+            self.listener.on_action(action).unwrap();
+        }
+    }
+
+    #[test]
+    fn moves_are_relayed_to_trackers() {
+        let fixture = setup(vec![]);
+        fixture.handle(&fixture.actions[1]);
+
+        let notification = fixture.notifications.try_recv().unwrap();
+        assert_eq!(notification.channel, "D1");
+        assert_eq!(notification.text, "Your card _Library hours extended_ has been moved from *Drafting* to *Copy Editing*.");
+        assert_eq!(notification.card.unwrap().url, "https://trello.com/c/CrD1sHrt");
+        assert!(fixture.notifications.try_recv().is_err());
+
+        let events = fixture.store.all("events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_array("recipients").unwrap(), &vec![Bson::String("U1".to_string())]);
+    }
+
+    #[test]
+    fn notifications_use_the_configured_template() {
+        let path = env::temp_dir().join("articlebot-relay-templates.json");
+        File::create(&path).unwrap().write_all(br#"{ "en": { "notify.moved": "{{actor}} moved {{writer}}'s {{card}} to {{to}} on {{board}}: {{url}}" } }"#).unwrap();
+        let fixture = setup_with_templates(vec![], Templates::load(path.to_str()).unwrap());
+        fixture.handle(&fixture.actions[1]);
+
+        assert_eq!(fixture.notifications.try_recv().unwrap().text,
+            "eddieeditor moved wendywriter's Library hours extended to Copy Editing on Articles: https://trello.com/c/CrD1sHrt");
+    }
+
+    #[test]
+    fn trackers_are_notified_in_their_language() {
+        let fixture = setup(vec![]);
+        LanguagePreferences::new(fixture.store.clone()).set("U1", "es").unwrap();
+        fixture.handle(&fixture.actions[1]);
+
+        assert_eq!(fixture.notifications.try_recv().unwrap().text, "Tu tarjeta _Library hours extended_ ha pasado de *Drafting* a *Copy Editing*.");
+    }
+
+    #[test]
+    fn muted_cards_are_not_relayed() {
+        let fixture = setup(vec!["CrD1sHrt"]);
+        fixture.handle(&fixture.actions[1]);
+
+        assert!(fixture.notifications.try_recv().is_err());
+        assert!(fixture.store.all("events")[0].get_array("recipients").unwrap().is_empty());
+    }
+
+    #[test]
+    fn other_actions_are_only_recorded() {
+        let fixture = setup(vec![]);
+        fixture.handle(&fixture.actions[2]);
+
+        assert!(fixture.notifications.try_recv().is_err());
+        assert!(fixture.trello.requests().is_empty());
+        assert_eq!(fixture.store.all("events")[0].get_str("type").unwrap(), "updateCard");
+    }
+
+    #[test]
+    fn subscriptions_are_notified_through_their_own_sink() {
+        let fixture = setup(vec!["CrD1sHrt"]);
+        let subscriptions = Subscriptions::new(fixture.store.clone());
+        for &(sink, target) in [("broken", "https://example.com/hook"), ("email", "wendy@example.com")].iter() {
+            subscriptions.add(&Subscription {
+                trello: "wendywriter".to_string(),
+                sink: sink.to_string(),
+                target: target.to_string(),
+                creator: "U2".to_string()
+            }).unwrap();
+        }
+        fixture.handle(&fixture.actions[1]);
+
+        // Muting only applies to the Slack tracker, and the broken sink doesn't keep the email from going out
+        assert!(fixture.notifications.try_recv().is_err());
+        assert_eq!(*fixture.deliveries.borrow(), vec!["wendy@example.com Your card _Library hours extended_ has been moved from *Drafting* to *Copy Editing*."]);
+        assert_eq!(fixture.store.all("events")[0].get_array("recipients").unwrap(), &vec![Bson::String("email:wendy@example.com".to_string())]);
+    }
+
+    #[test]
+    fn retried_moves_only_reach_the_trackers_that_missed_them() {
+        let fixture = setup(vec![]);
+        fixture.store.update_one("trello", doc! { "name": "wendywriter" }, doc! { "$push": { "trackers": "U2" } }, false).unwrap();
+        fixture.store.insert_one("slack", doc! { "uid": "U2", "cid": "D2", "tracking": "wendywriter", "muted": [] }).unwrap();
+
+        // Slack deliveries are recorded here instead, with the second tracker's failing at first
+        let deliveries = Rc::new(RefCell::new(Vec::new()));
+        let failing = Rc::new(RefCell::new(vec!["D2".to_string()]));
+        let mut sinks = NotificationSinks::new();
+        sinks.add(Box::new(RecordingSink { name: "slack", deliveries: Rc::clone(&deliveries), failing: Rc::clone(&failing) }));
+        let client : Arc<TrelloApi> = fixture.trello.clone();
+        let listener = RelayActionListener::new(fixture.store.clone(), Arc::new(CardHandler::new(client)), sinks, Templates::defaults());
+
+        fixture.history.on_action(&fixture.actions[1]).unwrap();
+        assert!(listener.on_action(&fixture.actions[1]).is_err());
+        failing.borrow_mut().clear();
+        listener.on_action(&fixture.actions[1]).unwrap();
+
+        let targets : Vec<String> = deliveries.borrow().iter().map(|delivery| delivery.split(' ').next().unwrap().to_string()).collect();
+        assert_eq!(targets, vec!["D1", "D2"]);
+        assert_eq!(fixture.store.all("events")[0].get_array("recipients").unwrap(),
+            &vec![Bson::String("U1".to_string()), Bson::String("U2".to_string())]);
+        assert!(fixture.store.all("relayed_actions").is_empty());
+    }
+
+    #[test]
+    fn moves_keep_the_card_members() {
+        let fixture = setup(vec![]);
+        fixture.history.on_action(&fixture.actions[1]).unwrap();
+
+        let events = fixture.store.all("events");
+        assert_eq!(events[0].get_str("to").unwrap(), "Copy Editing");
+        assert_eq!(events[0].get_array("members").unwrap(), &vec![Bson::String("wendywriter".to_string())]);
+        assert!(fixture.notifications.try_recv().is_err());
+    }
+
+    #[test]
+    fn moves_are_recorded_even_when_the_card_cannot_be_fetched() {
+        let fixture = setup(vec![]);
+        fixture.trello.respond_with(Method::Get, &format!("/cards/{}", CARD_ID), |_| Err(404));
+        fixture.history.on_action(&fixture.actions[1]).unwrap();
+
+        let events = fixture.store.all("events");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_str("from").unwrap(), "Drafting");
+        assert_eq!(events[0].get_str("to").unwrap(), "Copy Editing");
+        assert!(events[0].get_array("members").unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_moved_cards_are_prefetched() {
+        let fixture = setup(vec![]);
+        fixture.listener.prefetch(&fixture.actions).unwrap();
+
+        let requests = fixture.trello.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains(CARD_ID));
+        assert!(!requests[0].contains("5a3000000000000000000002"));
+    }
+}
@@ -1,39 +1,431 @@
-use serde_json::Value;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value, from_value};
+use chrono::prelude::*;
 
-#[derive(Deserialize)]
+// Trello only returns the fields that were asked for and adds new ones over time, so everything that
+// isn't an ID falls back to a default instead of failing the whole response
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Board {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub desc: String,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub short_link: String,
+    // Only present when the board is requested with lists, labels or members
+    #[serde(default)]
+    pub lists: Vec<List>,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    #[serde(default)]
+    pub members: Vec<Member>
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Card {
     pub id: String,
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
+    pub desc: String,
+    #[serde(default)]
     pub id_board: String,
+    #[serde(default)]
     pub id_list: String,
-    pub id_members: Vec<String>
+    #[serde(default)]
+    pub id_members: Vec<String>,
+    #[serde(default)]
+    pub id_labels: Vec<String>,
+    #[serde(default)]
+    pub short_link: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub pos: f64,
+    #[serde(default)]
+    pub due: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub due_complete: bool,
+    #[serde(default)]
+    pub date_last_activity: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    // Only present when the card is requested with members=true
+    #[serde(default)]
+    pub members: Vec<Member>,
+    // Only present when the card is requested with actions=createCard,copyCard, or filled in from a separate request in batches
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    // Only present when the card is requested with checklists=all or attachments=true
+    #[serde(default)]
+    pub checklists: Vec<Checklist>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct List {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub id_board: String,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub pos: f64
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Label {
+    pub id: String,
+    #[serde(default)]
+    pub id_board: String,
+    // Labels can be left unnamed, in which case only their colour tells them apart
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub color: Option<String>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Checklist {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub id_board: String,
+    #[serde(default)]
+    pub id_card: String,
+    #[serde(default)]
+    pub pos: f64,
+    #[serde(default)]
+    pub check_items: Vec<CheckItem>
+}
+
+impl Checklist {
+    pub fn completed(&self) -> usize {
+        self.check_items.iter().filter(|item| item.is_complete()).count()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckItem {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    // One of "complete" or "incomplete"
+    #[serde(default)]
+    pub state: String,
+    #[serde(default)]
+    pub pos: f64,
+    #[serde(default)]
+    pub due: Option<DateTime<Utc>>
+}
+
+impl CheckItem {
+    pub fn is_complete(&self) -> bool {
+        self.state == "complete"
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    #[serde(default)]
+    pub date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub id_member: String,
+    #[serde(default)]
+    pub is_upload: bool
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Member {
     pub id: String,
+    #[serde(default)]
     pub full_name: String,
+    #[serde(default)]
     pub initials: String,
+    #[serde(default)]
     pub username: String
 }
 
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Membership {
+    pub id: String,
+    pub id_member: String,
+    // One of "admin", "normal" or "observer"
+    #[serde(default)]
+    pub member_type: String,
+    pub member: Member
+}
+
 impl PartialEq for Member {
     fn eq(&self, other: &Member) -> bool {
         self.id == other.id
     }
 }
 
+#[derive(Deserialize, Clone)]
+pub struct BoardData {
+    pub id: String,
+    #[serde(default)]
+    pub name: String
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CardData {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub short_link: String
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ListData {
+    pub id: String,
+    // Older actions sometimes leave the name out, in which case the list has to be looked up
+    #[serde(default)]
+    pub name: Option<String>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateCardData {
+    pub card: CardData,
+    #[serde(default)]
+    pub list: Option<ListData>,
+    // Only present when the card was moved between lists
+    #[serde(default)]
+    pub list_before: Option<ListData>,
+    #[serde(default)]
+    pub list_after: Option<ListData>,
+    // The previous value of every field that changed
+    #[serde(default)]
+    pub old: Map<String, Value>,
+    // Trello puts the new values on the card, so these are picked out of it once the rest is parsed
+    #[serde(skip)]
+    pub new: Map<String, Value>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateCardData {
+    pub card: CardData,
+    #[serde(default)]
+    pub list: Option<ListData>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CommentCardData {
+    pub card: CardData,
+    #[serde(default)]
+    pub list: Option<ListData>,
+    pub text: String
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CardMemberData {
+    pub card: CardData,
+    pub id_member: String
+}
+
+#[derive(Clone)]
+pub enum ActionData {
+    CreateCard(CreateCardData),
+    CopyCard(CreateCardData),
+    UpdateCard(UpdateCardData),
+    CommentCard(CommentCardData),
+    AddMemberToCard(CardMemberData),
+    RemoveMemberFromCard(CardMemberData),
+    // Anything we don't act on (or that didn't have the shape we expected) is kept as it came
+    Other(Value)
+}
+
+impl ActionData {
+    fn parse(action_type: &str, data: Value) -> ActionData {
+        let parsed = match action_type {
+            "createCard" => from_value(data.clone()).map(ActionData::CreateCard),
+            "copyCard" => from_value(data.clone()).map(ActionData::CopyCard),
+            "updateCard" => from_value::<UpdateCardData>(data.clone()).map(|mut update| {
+                for field in update.old.keys() {
+                    if let Some(value) = data.get("card").and_then(|card| card.get(field)) {
+                        update.new.insert(field.clone(), value.clone());
+                    }
+                }
+                ActionData::UpdateCard(update)
+            }),
+            "commentCard" => from_value(data.clone()).map(ActionData::CommentCard),
+            "addMemberToCard" => from_value(data.clone()).map(ActionData::AddMemberToCard),
+            "removeMemberFromCard" => from_value(data.clone()).map(ActionData::RemoveMemberFromCard),
+            _ => return ActionData::Other(data)
+        };
+
+        match parsed {
+            Ok(action_data) => action_data,
+            Err(e) => {
+                warn!("Unexpected data for a {} action: {}", action_type, e);
+                ActionData::Other(data)
+            }
+        }
+    }
+
+    pub fn card(&self) -> Option<&CardData> {
+        match *self {
+            ActionData::CreateCard(ref data) | ActionData::CopyCard(ref data) => Some(&data.card),
+            ActionData::UpdateCard(ref data) => Some(&data.card),
+            ActionData::CommentCard(ref data) => Some(&data.card),
+            ActionData::AddMemberToCard(ref data) | ActionData::RemoveMemberFromCard(ref data) => Some(&data.card),
+            ActionData::Other(_) => None
+        }
+    }
+
+    // The lists a card was moved between, if this action is a move
+    pub fn list_move(&self) -> Option<(&ListData, &ListData)> {
+        match *self {
+            ActionData::UpdateCard(UpdateCardData { list_before: Some(ref before), list_after: Some(ref after), .. }) => Some((before, after)),
+            _ => None
+        }
+    }
+}
+
 #[derive(Deserialize)]
+struct RawAction {
+    id: String,
+    data: Value,
+    date: DateTime<Utc>,
+    #[serde(rename = "type")]
+    action_type: String,
+    #[serde(rename = "idMemberCreator")]
+    creator_id: String,
+    #[serde(rename = "memberCreator")]
+    creator: Member
+}
+
+#[derive(Clone)]
 pub struct Action {
     pub id: String,
-    pub data: Value,
-    pub date: String, // TODO: Switch to using DateTime<Utc> later
-    #[serde(rename = "type")]
+    pub data: ActionData,
+    // Every action on a board carries the board it happened on, whatever its type
+    pub board: Option<BoardData>,
+    pub date: DateTime<Utc>,
     pub action_type: String,
-    #[serde(rename = "idMemberCreator")]
     pub creator_id: String,
-    #[serde(rename = "memberCreator")]
     pub creator: Member
-}
\ No newline at end of file
+}
+
+// Action data only makes sense alongside the action's type, so it is parsed once both are known
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Action, D::Error> {
+        let raw = RawAction::deserialize(deserializer)?;
+        Ok(Action {
+            board: raw.data.get("board").and_then(|board| from_value(board.clone()).ok()),
+            data: ActionData::parse(&raw.action_type, raw.data),
+            id: raw.id,
+            date: raw.date,
+            action_type: raw.action_type,
+            creator_id: raw.creator_id,
+            creator: raw.creator
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trello_fake::fixture;
+
+    use serde_json;
+
+    #[test]
+    fn action_data_is_parsed_by_type() {
+        let actions : Vec<Action> = serde_json::from_str(&fixture("board_actions.json")).unwrap();
+
+        match actions[0].data {
+            ActionData::CommentCard(ref data) => assert_eq!(data.text, "Needs a second source."),
+            _ => panic!("expected a comment")
+        }
+
+        let (before, after) = actions[1].data.list_move().unwrap();
+        assert_eq!(before.name, Some("Drafting".to_string()));
+        assert_eq!(after.name, Some("Copy Editing".to_string()));
+        assert_eq!(actions[1].date, Utc.ymd(2018, 6, 1).and_hms(12, 15, 0));
+        assert_eq!(actions[1].board.as_ref().unwrap().name, "Articles");
+
+        match actions[2].data {
+            ActionData::UpdateCard(ref data) => {
+                assert!(data.list_before.is_none());
+                assert_eq!(data.old.get("name"), Some(&json!("Budget story")));
+                assert_eq!(data.new.get("name"), Some(&json!("Campus budget explained")));
+            }
+            _ => panic!("expected a card update")
+        }
+    }
+
+    #[test]
+    fn unexpected_action_data_is_kept() {
+        let action : Action = serde_json::from_value(json!({
+            "id": "1",
+            "data": { "text": "no card here" },
+            "type": "commentCard",
+            "date": "2018-06-01T12:00:00.000Z",
+            "idMemberCreator": "2",
+            "memberCreator": { "id": "2", "username": "someone" }
+        })).unwrap();
+
+        assert!(action.data.card().is_none());
+        assert!(action.board.is_none());
+        match action.data {
+            ActionData::Other(ref data) => assert_eq!(data["text"], "no card here"),
+            _ => panic!("expected the raw data")
+        }
+    }
+
+    #[test]
+    fn cards_tolerate_missing_and_unknown_fields() {
+        let card : Card = serde_json::from_value(json!({
+            "id": "1",
+            "name": "Partial card",
+            "cover": { "color": null },
+            "badges": { "votes": 0 }
+        })).unwrap();
+
+        assert_eq!(card.name, "Partial card");
+        assert!(card.due.is_none());
+        assert!(card.labels.is_empty());
+
+        let card : Card = serde_json::from_str(&fixture("card.json")).unwrap();
+        assert_eq!(card.labels[0].name, "News");
+        assert_eq!(card.pos, 16384.0);
+    }
+}